@@ -0,0 +1,437 @@
+// Compares RBTree in both update modes, LLRBTree and the AVL and WAVL policies with BTreeSet, a BTreeMap counting duplicates and a sorted Vec.
+//
+// Runs 1K and 1M elements by default. Set RBTREE_BENCH_10M=1 to also run 10M, which takes a long time.
+
+use std::collections::{BTreeMap, BTreeSet};
+
+use criterion::{black_box, criterion_group, criterion_main, BatchSize, BenchmarkId, Criterion};
+use rbtree::balanced::{Avl, Wavl};
+use rbtree::{BalancedTree, LLRBTree, RBTree, UpdateMode};
+
+// Inserting into or deleting from the middle of a Vec is O(n), so the sorted Vec
+// only runs those benchmarks up to this size.
+const SORTED_VEC_EDIT_LIMIT: usize = 100_000;
+
+trait Collection {
+    const NAME: &'static str;
+
+    fn new() -> Self;
+    // The fastest way each collection has to build from values that are not sorted yet.
+    fn build(values: &[u64]) -> Self;
+    fn insert(&mut self, value: u64);
+    fn delete(&mut self, value: u64) -> bool;
+    fn contains(&self, value: u64) -> bool;
+    // Wrapping, the random values use the whole u64 range.
+    fn sum(&self) -> u64;
+}
+
+impl Collection for RBTree<u64> {
+    const NAME: &'static str = "RBTree";
+
+    fn new() -> Self {
+        RBTree::new()
+    }
+
+    fn build(values: &[u64]) -> Self {
+        values.iter().copied().collect()
+    }
+
+    fn insert(&mut self, value: u64) {
+        RBTree::insert(self, value);
+    }
+
+    fn delete(&mut self, value: u64) -> bool {
+        RBTree::delete(self, value)
+    }
+
+    fn contains(&self, value: u64) -> bool {
+        RBTree::contains(self, value)
+    }
+
+    fn sum(&self) -> u64 {
+        self.iter().fold(0, |sum, value| sum.wrapping_add(*value))
+    }
+}
+
+// The same tree, rebalancing on the way down.
+struct TopDown(RBTree<u64>);
+
+impl Collection for TopDown {
+    const NAME: &'static str = "RBTree top-down";
+
+    fn new() -> Self {
+        let mut tree = RBTree::new();
+        tree.set_mode(UpdateMode::TopDown);
+        TopDown(tree)
+    }
+
+    fn build(values: &[u64]) -> Self {
+        let mut tree: RBTree<u64> = values.iter().copied().collect();
+        tree.set_mode(UpdateMode::TopDown);
+        TopDown(tree)
+    }
+
+    fn insert(&mut self, value: u64) {
+        self.0.insert(value);
+    }
+
+    fn delete(&mut self, value: u64) -> bool {
+        self.0.delete(value)
+    }
+
+    fn contains(&self, value: u64) -> bool {
+        self.0.contains(value)
+    }
+
+    fn sum(&self) -> u64 {
+        self.0.iter().fold(0, |sum, value| sum.wrapping_add(*value))
+    }
+}
+
+impl Collection for LLRBTree<u64> {
+    const NAME: &'static str = "LLRBTree";
+
+    fn new() -> Self {
+        LLRBTree::new()
+    }
+
+    fn build(values: &[u64]) -> Self {
+        values.iter().copied().collect()
+    }
+
+    fn insert(&mut self, value: u64) {
+        LLRBTree::insert(self, value);
+    }
+
+    fn delete(&mut self, value: u64) -> bool {
+        LLRBTree::delete(self, value)
+    }
+
+    fn contains(&self, value: u64) -> bool {
+        LLRBTree::contains(self, value)
+    }
+
+    fn sum(&self) -> u64 {
+        self.iter().fold(0, |sum, value| sum.wrapping_add(*value))
+    }
+}
+
+impl Collection for BalancedTree<u64, Avl> {
+    const NAME: &'static str = "AVL";
+
+    fn new() -> Self {
+        BalancedTree::new()
+    }
+
+    fn build(values: &[u64]) -> Self {
+        values.iter().copied().collect()
+    }
+
+    fn insert(&mut self, value: u64) {
+        BalancedTree::insert(self, value);
+    }
+
+    fn delete(&mut self, value: u64) -> bool {
+        BalancedTree::delete(self, value)
+    }
+
+    fn contains(&self, value: u64) -> bool {
+        BalancedTree::contains(self, &value)
+    }
+
+    fn sum(&self) -> u64 {
+        self.iter().fold(0, |sum, value| sum.wrapping_add(*value))
+    }
+}
+
+impl Collection for BalancedTree<u64, Wavl> {
+    const NAME: &'static str = "WAVL";
+
+    fn new() -> Self {
+        BalancedTree::new()
+    }
+
+    fn build(values: &[u64]) -> Self {
+        values.iter().copied().collect()
+    }
+
+    fn insert(&mut self, value: u64) {
+        BalancedTree::insert(self, value);
+    }
+
+    fn delete(&mut self, value: u64) -> bool {
+        BalancedTree::delete(self, value)
+    }
+
+    fn contains(&self, value: u64) -> bool {
+        BalancedTree::contains(self, &value)
+    }
+
+    fn sum(&self) -> u64 {
+        self.iter().fold(0, |sum, value| sum.wrapping_add(*value))
+    }
+}
+
+impl Collection for BTreeSet<u64> {
+    const NAME: &'static str = "BTreeSet";
+
+    fn new() -> Self {
+        BTreeSet::new()
+    }
+
+    fn build(values: &[u64]) -> Self {
+        values.iter().copied().collect()
+    }
+
+    fn insert(&mut self, value: u64) {
+        BTreeSet::insert(self, value);
+    }
+
+    fn delete(&mut self, value: u64) -> bool {
+        self.remove(&value)
+    }
+
+    fn contains(&self, value: u64) -> bool {
+        BTreeSet::contains(self, &value)
+    }
+
+    fn sum(&self) -> u64 {
+        self.iter().fold(0, |sum, value| sum.wrapping_add(*value))
+    }
+}
+
+// Keeps duplicates like RBTree does, by counting them.
+impl Collection for BTreeMap<u64, usize> {
+    const NAME: &'static str = "BTreeMap";
+
+    fn new() -> Self {
+        BTreeMap::new()
+    }
+
+    fn build(values: &[u64]) -> Self {
+        let mut map = BTreeMap::new();
+        for value in values {
+            *map.entry(*value).or_insert(0) += 1;
+        }
+        map
+    }
+
+    fn insert(&mut self, value: u64) {
+        *self.entry(value).or_insert(0) += 1;
+    }
+
+    fn delete(&mut self, value: u64) -> bool {
+        match self.get_mut(&value) {
+            Some(1) => self.remove(&value).is_some(),
+            Some(count) => {
+                *count -= 1;
+                true
+            },
+            None => false,
+        }
+    }
+
+    fn contains(&self, value: u64) -> bool {
+        self.contains_key(&value)
+    }
+
+    fn sum(&self) -> u64 {
+        self.iter().fold(0, |sum, (value, count)| sum.wrapping_add(value.wrapping_mul(*count as u64)))
+    }
+}
+
+struct SortedVec(Vec<u64>);
+
+impl Collection for SortedVec {
+    const NAME: &'static str = "SortedVec";
+
+    fn new() -> Self {
+        SortedVec(Vec::new())
+    }
+
+    fn build(values: &[u64]) -> Self {
+        let mut values = values.to_vec();
+        values.sort();
+        SortedVec(values)
+    }
+
+    fn insert(&mut self, value: u64) {
+        let index = self.0.partition_point(|other| *other <= value);
+        self.0.insert(index, value);
+    }
+
+    fn delete(&mut self, value: u64) -> bool {
+        match self.0.binary_search(&value) {
+            Ok(index) => {
+                self.0.remove(index);
+                true
+            },
+            Err(_) => false,
+        }
+    }
+
+    fn contains(&self, value: u64) -> bool {
+        self.0.binary_search(&value).is_ok()
+    }
+
+    fn sum(&self) -> u64 {
+        self.0.iter().fold(0, |sum, value| sum.wrapping_add(*value))
+    }
+}
+
+fn sizes() -> Vec<usize> {
+    let mut sizes = vec![1_000, 1_000_000];
+    if std::env::var_os("RBTREE_BENCH_10M").is_some() {
+        sizes.push(10_000_000);
+    }
+    sizes
+}
+
+// xorshift, so the benchmarks need no rand dependency and see the same values on every run
+fn random_values(len: usize, seed: u64) -> Vec<u64> {
+    let mut state = seed;
+    (0..len)
+        .map(|_| {
+            state ^= state << 13;
+            state ^= state >> 7;
+            state ^= state << 17;
+            state
+        })
+        .collect()
+}
+
+#[derive(Clone, Copy)]
+enum Pattern {
+    Sequential,
+    Random,
+    // about a hundred copies of every value
+    Duplicates,
+}
+
+impl Pattern {
+    const ALL: [Pattern; 3] = [Pattern::Sequential, Pattern::Random, Pattern::Duplicates];
+
+    fn name(self) -> &'static str {
+        match self {
+            Pattern::Sequential => "sequential",
+            Pattern::Random => "random",
+            Pattern::Duplicates => "duplicates",
+        }
+    }
+
+    fn values(self, len: usize) -> Vec<u64> {
+        match self {
+            Pattern::Sequential => (0..len as u64).collect(),
+            Pattern::Random => random_values(len, 0x2545_f491_4f6c_dd1d),
+            Pattern::Duplicates => {
+                let distinct = (len / 100).max(1) as u64;
+                random_values(len, 0x9e37_79b9_7f4a_7c15).into_iter().map(|value| value % distinct).collect()
+            },
+        }
+    }
+}
+
+fn bench_insert<C: Collection>(c: &mut Criterion, pattern: Pattern, len: usize) {
+    if C::NAME == SortedVec::NAME && len > SORTED_VEC_EDIT_LIMIT {
+        return;
+    }
+    let values = pattern.values(len);
+    let mut group = c.benchmark_group(format!("insert/{}", pattern.name()));
+    configure(&mut group, len);
+    group.bench_function(BenchmarkId::new(C::NAME, len), |b| {
+        b.iter_with_large_drop(|| {
+            let mut collection = C::new();
+            for value in values.iter() {
+                collection.insert(*value);
+            }
+            collection
+        })
+    });
+    group.finish();
+}
+
+fn bench_delete<C: Collection>(c: &mut Criterion, pattern: Pattern, len: usize) {
+    if C::NAME == SortedVec::NAME && len > SORTED_VEC_EDIT_LIMIT {
+        return;
+    }
+    let values = pattern.values(len);
+    // deleted in a different order than they were inserted
+    let mut order = values.clone();
+    order.reverse();
+    let mut group = c.benchmark_group(format!("delete/{}", pattern.name()));
+    configure(&mut group, len);
+    group.bench_function(BenchmarkId::new(C::NAME, len), |b| {
+        b.iter_batched(
+            || C::build(&values),
+            |mut collection| {
+                for value in order.iter() {
+                    black_box(collection.delete(*value));
+                }
+                collection
+            },
+            BatchSize::LargeInput,
+        )
+    });
+    group.finish();
+}
+
+fn bench_lookup<C: Collection>(c: &mut Criterion, pattern: Pattern, len: usize) {
+    let values = pattern.values(len);
+    let collection = C::build(&values);
+    // half of the lookups miss
+    let lookups: Vec<u64> = values.iter().zip(random_values(len, 7)).take(10_000).flat_map(|(hit, miss)| [*hit, miss]).collect();
+    let mut group = c.benchmark_group(format!("lookup/{}", pattern.name()));
+    configure(&mut group, len);
+    group.bench_function(BenchmarkId::new(C::NAME, len), |b| {
+        b.iter(|| lookups.iter().filter(|value| collection.contains(**value)).count())
+    });
+    group.finish();
+}
+
+fn bench_iter<C: Collection>(c: &mut Criterion, len: usize) {
+    let collection = C::build(&Pattern::Random.values(len));
+    let mut group = c.benchmark_group("iter");
+    configure(&mut group, len);
+    group.bench_function(BenchmarkId::new(C::NAME, len), |b| b.iter(|| collection.sum()));
+    group.finish();
+}
+
+fn bench_build<C: Collection>(c: &mut Criterion, len: usize) {
+    let values = Pattern::Random.values(len);
+    let mut group = c.benchmark_group("build");
+    configure(&mut group, len);
+    group.bench_function(BenchmarkId::new(C::NAME, len), |b| b.iter_with_large_drop(|| C::build(&values)));
+    group.finish();
+}
+
+fn configure(group: &mut criterion::BenchmarkGroup<'_, criterion::measurement::WallTime>, len: usize) {
+    if len >= 1_000_000 {
+        group.sample_size(10);
+    }
+}
+
+fn bench_collection<C: Collection>(c: &mut Criterion) {
+    for len in sizes() {
+        for pattern in Pattern::ALL {
+            bench_insert::<C>(c, pattern, len);
+            bench_delete::<C>(c, pattern, len);
+            bench_lookup::<C>(c, pattern, len);
+        }
+        bench_iter::<C>(c, len);
+        bench_build::<C>(c, len);
+    }
+}
+
+fn collections(c: &mut Criterion) {
+    bench_collection::<RBTree<u64>>(c);
+    bench_collection::<TopDown>(c);
+    bench_collection::<LLRBTree<u64>>(c);
+    bench_collection::<BalancedTree<u64, Avl>>(c);
+    bench_collection::<BalancedTree<u64, Wavl>>(c);
+    bench_collection::<BTreeSet<u64>>(c);
+    bench_collection::<BTreeMap<u64, usize>>(c);
+    bench_collection::<SortedVec>(c);
+}
+
+criterion_group!(benches, collections);
+criterion_main!(benches);
@@ -0,0 +1,82 @@
+#![no_main]
+
+use std::ops::{Bound, RangeBounds};
+
+use arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+use rbtree::RBTree;
+
+mod model;
+
+use model::{check, Model};
+
+#[derive(Arbitrary, Debug)]
+enum Op {
+    Insert(u8),
+    Delete(u8),
+    RemoveRange(Bound<u8>, Bound<u8>),
+    // keeps the values divisible by the number
+    Retain(u8),
+    // takes out the values divisible by the number
+    ExtractIf(u8),
+    Drain,
+    // inserts (true) or deletes (false) the values, then commits or rolls back
+    Transaction(Vec<(bool, u8)>, bool),
+}
+
+fuzz_target!(|ops: Vec<Op>| {
+    let mut tree = RBTree::new();
+    let mut model = Model::default();
+
+    for op in ops {
+        match op {
+            Op::Insert(value) => {
+                tree.insert(value);
+                model.insert(value);
+            },
+            Op::Delete(value) => assert_eq!(tree.delete(value), model.remove(value)),
+            Op::RemoveRange(start, end) => {
+                let range = (start, end);
+                let expected = model.values().iter().filter(|value| range.contains(*value)).count();
+                assert_eq!(tree.remove_range(range), expected);
+                model.retain(|value| !range.contains(&value));
+            },
+            Op::Retain(divisor) => {
+                let divisor = divisor.max(1);
+                tree.retain(|value| value % divisor == 0);
+                model.retain(|value| value % divisor == 0);
+            },
+            Op::ExtractIf(divisor) => {
+                let divisor = divisor.max(1);
+                let expected: Vec<u8> = model.values().into_iter().filter(|value| value % divisor == 0).collect();
+                assert_eq!(tree.extract_if(|value| value % divisor == 0).collect::<Vec<u8>>(), expected);
+                model.retain(|value| value % divisor != 0);
+            },
+            Op::Drain => {
+                assert_eq!(tree.drain().collect::<Vec<u8>>(), model.values());
+                model = Model::default();
+            },
+            Op::Transaction(changes, commit) => {
+                let before = model.values();
+                let result = tree.transaction(|tx| {
+                    for (insert, value) in changes.iter().copied() {
+                        if insert {
+                            tx.insert(value);
+                            model.insert(value);
+                        } else {
+                            assert_eq!(tx.delete(value), model.remove(value));
+                        }
+                    }
+                    if commit { Ok(()) } else { Err(()) }
+                });
+                assert_eq!(result.is_ok(), commit);
+                if !commit {
+                    model = Model::default();
+                    before.into_iter().for_each(|value| model.insert(value));
+                }
+            },
+        }
+        check(&tree, &model);
+        assert_eq!(tree.iter().copied().collect::<Vec<u8>>(), model.values());
+    }
+});
@@ -0,0 +1,55 @@
+#![allow(dead_code)]
+
+// Reference model shared by the targets: a BTreeMap counting how often each value was inserted.
+
+use std::collections::BTreeMap;
+
+use rbtree::RBTree;
+
+#[derive(Default)]
+pub struct Model {
+    counts: BTreeMap<u8, usize>,
+}
+
+impl Model {
+    pub fn insert(&mut self, value: u8) {
+        *self.counts.entry(value).or_insert(0) += 1;
+    }
+
+    pub fn remove(&mut self, value: u8) -> bool {
+        match self.counts.get_mut(&value) {
+            Some(count) => {
+                *count -= 1;
+                if *count == 0 {
+                    self.counts.remove(&value);
+                }
+                true
+            },
+            None => false,
+        }
+    }
+
+    pub fn contains(&self, value: u8) -> bool {
+        self.counts.contains_key(&value)
+    }
+
+    pub fn len(&self) -> usize {
+        self.counts.values().sum()
+    }
+
+    pub fn retain<F: FnMut(u8) -> bool>(&mut self, mut f: F) {
+        self.counts.retain(|value, _| f(*value));
+    }
+
+    pub fn values(&self) -> Vec<u8> {
+        self.counts.iter().flat_map(|(value, count)| std::iter::repeat(*value).take(*count)).collect()
+    }
+}
+
+// Runs after every operation. The full contents are compared by the targets when they iterate.
+pub fn check(tree: &RBTree<u8>, model: &Model) {
+    if let Err(violation) = tree.validate() {
+        panic!("{}", violation);
+    }
+    assert_eq!(tree.iter().count(), model.len());
+}
@@ -0,0 +1,41 @@
+#![no_main]
+
+use arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+use rbtree::{RBTree, UpdateMode};
+
+mod model;
+
+use model::{check, Model};
+
+#[derive(Arbitrary, Debug)]
+enum Op {
+    Insert(u8),
+    Delete(u8),
+    Take(u8),
+    Contains(u8),
+    Iterate,
+    TopDown(bool),
+}
+
+fuzz_target!(|ops: Vec<Op>| {
+    let mut tree = RBTree::new();
+    let mut model = Model::default();
+
+    for op in ops {
+        match op {
+            Op::Insert(value) => {
+                tree.insert(value);
+                model.insert(value);
+            },
+            Op::Delete(value) => assert_eq!(tree.delete(value), model.remove(value)),
+            Op::Take(value) => assert_eq!(tree.take(&value), Some(value).filter(|_| model.remove(value))),
+            Op::Contains(value) => assert_eq!(tree.contains(value), model.contains(value)),
+            Op::Iterate => assert_eq!(tree.iter().copied().collect::<Vec<u8>>(), model.values()),
+            Op::TopDown(top_down) => tree.set_mode(if top_down { UpdateMode::TopDown } else { UpdateMode::BottomUp }),
+        }
+        check(&tree, &model);
+    }
+
+    assert_eq!(tree.into_iter().collect::<Vec<u8>>(), model.values());
+});
@@ -0,0 +1,69 @@
+use super::policy::Policy;
+use super::*;
+
+/// AVL rules: the heights of the two subtrees of every node differ by at most one. The rank of
+/// a node is its height, with leaves at 0.
+pub struct Avl;
+
+fn update<T>(node: &mut Node<T, i32>) {
+    node.rank = 1 + rank(node.left.as_deref()).max(rank(node.right.as_deref()));
+}
+
+fn rotate_updating<T>(node: Box<Node<T, i32>>, dir: Direction) -> Box<Node<T, i32>> {
+    let mut top = rotate(node, dir);
+    update(top.link(dir).as_mut().unwrap());
+    update(&mut top);
+    top
+}
+
+// Rebalances a node whose subtree heights differ by at most two. Returns whether its height changed.
+fn rebalance<T>(mut node: Box<Node<T, i32>>) -> (Box<Node<T, i32>>, bool) {
+    let height = node.rank;
+    let balance = rank(node.left.as_deref()) - rank(node.right.as_deref());
+    if balance.abs() > 1 {
+        let heavy = if balance > 0 { Direction::Left } else { Direction::Right };
+        let child = node.child(heavy).unwrap();
+        // a child leaning the other way is rotated first, which makes it a double rotation
+        if rank(child.child(heavy)) < rank(child.child(heavy.opposite())) {
+            let child = node.link(heavy).take().unwrap();
+            *node.link(heavy) = Some(rotate_updating(child, heavy));
+        }
+        node = rotate_updating(node, heavy.opposite());
+    } else {
+        update(&mut node);
+    }
+    let changed = node.rank != height;
+    (node, changed)
+}
+
+// `changed` means the height of the subtree changed.
+impl Policy for Avl {
+    type Rank = i32;
+
+    fn leaf() -> i32 {
+        0
+    }
+
+    fn inserted<T>(node: Box<Node<T, i32>>, _dir: Direction) -> (Box<Node<T, i32>>, bool) {
+        rebalance(node)
+    }
+
+    fn removed<T>(_rank: i32, child: Link<T, i32>) -> (Link<T, i32>, bool) {
+        (child, true)
+    }
+
+    fn deleted<T>(node: Box<Node<T, i32>>, _dir: Direction) -> (Box<Node<T, i32>>, bool) {
+        rebalance(node)
+    }
+
+    // splitting in the middle keeps the heights of every node's subtrees within one
+    fn built(height: i32, _lowest: bool) -> i32 {
+        height
+    }
+
+    fn validate<T: Ord>(root: &Node<T, i32>) -> Result<(), InvariantViolation<'_, T>> {
+        check_rank_differences(root, &mut Vec::new(), |left, right, _| matches!((left, right), (1, 1) | (1, 2) | (2, 1)))
+    }
+}
+
+impl Balance for Avl {}
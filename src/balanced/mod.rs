@@ -0,0 +1,644 @@
+//! A binary search tree with pluggable balancing rules.
+//!
+//! `BalancedTree<T, P>` has the ordered multiset API of `RBTree`, while the policy `P` decides
+//! what every node stores about its balance and how the tree is rebalanced after a change:
+//!
+//! - `RedBlack` keeps a `Color` per node, like `RBTree`.
+//! - `Avl` keeps the height. Its trees are the flattest of the three, which suits read-heavy use.
+//! - `Wavl` keeps a rank that only bounds the height. It rotates as rarely as red-black after
+//!   deletes, and builds exactly the AVL tree when there are none.
+//!
+//! It is a separate tree type, not `RBTree` with a policy parameter. It shares the lookups, the
+//! order statistics, the nearest-value queries and the bulk removals with `RBTree`, through
+//! the subtree sizes both keep. What is specific to `RBTree` stays there: observers and
+//! tracing, transactions, top-down updates, custom allocators and the map on top of it.
+
+use alloc::boxed::Box;
+use alloc::vec::Vec;
+use core::borrow::Borrow;
+use core::fmt;
+use core::cmp::Ordering;
+use core::iter::FromIterator;
+use core::ops::RangeBounds;
+
+use crate::tree::{
+    count_while, k_nearest, nearest, range_positions, red_depth, remove_range, search, select, size, validate_order, validate_sizes, Direction,
+    ExtractIf, InvariantViolation, Position, Positional, SearchNode, SizedNode, Target,
+};
+
+mod avl;
+mod red_black;
+mod wavl;
+
+pub use avl::Avl;
+pub use red_black::RedBlack;
+pub use wavl::Wavl;
+
+use policy::{Link, Node};
+
+// Moves `node` down to the `dir` side of its other child, which takes its place. Ranks are left
+// to the policy, the new top takes over the size of the subtree.
+fn rotate<T, R>(mut node: Box<Node<T, R>>, dir: Direction) -> Box<Node<T, R>> {
+    let mut top = node.link(dir.opposite()).take().expect("Rotation needs the child on the other side");
+    *node.link(dir.opposite()) = top.link(dir).take();
+    top.size = node.size;
+    node.update_size();
+    *top.link(dir) = Some(node);
+    top
+}
+
+// Rank of a child for the rank-based policies, missing children have rank -1.
+fn rank<T>(node: Option<&Node<T, i32>>) -> i32 {
+    node.map_or(-1, |node| node.rank)
+}
+
+// Checks that every pair of child rank differences is `allowed`.
+fn check_rank_differences<'a, T>(node: &'a Node<T, i32>, path: &mut Vec<Direction>, allowed: fn(i32, i32, bool) -> bool) -> Result<(), InvariantViolation<'a, T>> {
+    let left = node.rank - rank(node.left.as_deref());
+    let right = node.rank - rank(node.right.as_deref());
+    if !allowed(left, right, node.is_leaf()) {
+        return Err(InvariantViolation::RankDifference { value: &node.value, path: path.clone(), left, right });
+    }
+    for dir in [Direction::Left, Direction::Right].iter() {
+        if let Some(child) = node.child(*dir) {
+            path.push(*dir);
+            check_rank_differences(child, path, allowed)?;
+            path.pop();
+        }
+    }
+    Ok(())
+}
+
+// Public only inside this private module, so policies can't be implemented or named elsewhere.
+mod policy {
+    use super::*;
+
+    pub type Link<T, R> = Option<Box<Node<T, R>>>;
+
+    pub struct Node<T, R> {
+        pub value: T,
+        pub rank: R,
+        pub left: Link<T, R>,
+        pub right: Link<T, R>,
+        // Number of nodes in the subtree rooted here, for the order statistics.
+        pub size: usize,
+    }
+
+    impl<T, R> Node<T, R> {
+        pub fn child(&self, dir: Direction) -> Option<&Node<T, R>> {
+            match dir {
+                Direction::Left => self.left.as_deref(),
+                Direction::Right => self.right.as_deref(),
+            }
+        }
+
+        pub fn link(&mut self, dir: Direction) -> &mut Link<T, R> {
+            match dir {
+                Direction::Left => &mut self.left,
+                Direction::Right => &mut self.right,
+            }
+        }
+
+        pub fn is_leaf(&self) -> bool {
+            self.left.is_none() && self.right.is_none()
+        }
+
+        // Recomputes `size` from the children, whose sizes have to be right already.
+        pub fn update_size(&mut self) {
+            self.size = 1 + self.left.as_ref().map_or(0, |left| left.size) + self.right.as_ref().map_or(0, |right| right.size);
+        }
+    }
+
+    impl<T: Ord, R> SearchNode for Node<T, R> {
+        type Value = T;
+
+        fn value(&self) -> &T {
+            &self.value
+        }
+
+        fn child(&self, dir: Direction) -> Option<&Self> {
+            Node::child(self, dir)
+        }
+    }
+
+    impl<T: Ord, R> SizedNode for Node<T, R> {
+        fn size(&self) -> usize {
+            self.size
+        }
+    }
+
+    // The rebalancing steps of a policy. `changed` flags tell the parent whether it has to look
+    // at the subtree again, the policy decides what they mean.
+    pub trait Policy {
+        type Rank: Copy + PartialEq + fmt::Debug;
+
+        // The rank of a new node.
+        fn leaf() -> Self::Rank;
+
+        // The subtree in `dir` got a new node and reported a change.
+        fn inserted<T>(node: Box<Node<T, Self::Rank>>, dir: Direction) -> (Box<Node<T, Self::Rank>>, bool);
+
+        // A node with `rank` and at most one child is replaced by that child.
+        fn removed<T>(rank: Self::Rank, child: Link<T, Self::Rank>) -> (Link<T, Self::Rank>, bool);
+
+        // The subtree in `dir` lost a node and reported a change.
+        fn deleted<T>(node: Box<Node<T, Self::Rank>>, dir: Direction) -> (Box<Node<T, Self::Rank>>, bool);
+
+        // Called on the root after every insert and delete.
+        fn finish<T>(_root: &mut Node<T, Self::Rank>) {}
+
+        // The rank of a node in a tree built from sorted values by splitting them in the middle,
+        // from the height of its subtree, with leaves at 0, and whether the node is on the
+        // lowest level while that level is not full.
+        fn built(height: i32, lowest: bool) -> Self::Rank;
+
+        // Checks the balancing rules, the order is checked by the tree.
+        fn validate<T: Ord>(root: &Node<T, Self::Rank>) -> Result<(), InvariantViolation<'_, T>>;
+    }
+}
+
+/// Balancing rules for a `BalancedTree`: `RedBlack`, `Avl` or `Wavl`.
+pub trait Balance: policy::Policy {}
+
+/// An ordered multiset balanced by the policy `P`, see the module documentation.
+pub struct BalancedTree<T: Ord, P: Balance = RedBlack> {
+    root: Link<T, P::Rank>,
+}
+
+impl<T: Ord, P: Balance> BalancedTree<T, P> {
+    pub fn new() -> BalancedTree<T, P> {
+        BalancedTree { root: None }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.root.is_none()
+    }
+
+    /// Number of values in the tree, duplicates included.
+    pub fn len(&self) -> usize {
+        size(self.root.as_deref())
+    }
+
+    pub fn iter(&self) -> Iter<'_, T, P> {
+        let mut iter = Iter { stack: Vec::new() };
+        iter.push_left_spine(self.root.as_deref());
+        iter
+    }
+
+    /// The values inside `range` in order. Finding the first one and counting them takes O(log n).
+    pub fn range<R: RangeBounds<T>>(&self, range: R) -> Range<'_, T, P> {
+        let (start, end) = range_positions(self.root.as_deref(), &range);
+
+        // the stack `iter` would have after yielding the first `start` values
+        let mut iter = Iter { stack: Vec::new() };
+        let mut index = start;
+        let mut next = self.root.as_deref();
+        while let Some(node) = next {
+            let left_size = size(node.left.as_deref());
+            if index <= left_size {
+                iter.stack.push(node);
+            }
+            match index.cmp(&left_size) {
+                Ordering::Less => next = node.left.as_deref(),
+                Ordering::Equal => break,
+                Ordering::Greater => {
+                    index -= left_size + 1;
+                    next = node.right.as_deref();
+                },
+            }
+        }
+        Range { iter, remaining: end - start }
+    }
+
+    /// A value equal to `value`, which can be any borrowed form of the values, as long as it
+    /// orders the same way.
+    pub fn get<Q: ?Sized + Ord>(&self, value: &Q) -> Option<&T>
+    where
+        T: Borrow<Q>,
+    {
+        search(self.root.as_deref(), |node_value| value.cmp(node_value.borrow()))
+    }
+
+    pub fn contains(&self, value: &T) -> bool {
+        self.get(value).is_some()
+    }
+
+    /// The value at `index` in sorted order, counting from 0, in O(log n).
+    pub fn select(&self, index: usize) -> Option<&T> {
+        select(self.root.as_deref(), index)
+    }
+
+    /// Number of values less than `value`, which is the index of its first copy in sorted
+    /// order if it is in the tree. O(log n).
+    pub fn rank(&self, value: &T) -> usize {
+        count_while(self.root.as_deref(), |node_value| node_value < value)
+    }
+
+    /// The value closest to `query` by `distance`, in O(log n), like `RBTree::nearest`.
+    pub fn nearest<D: PartialOrd, F: Fn(&T, &T) -> D>(&self, query: &T, distance: F) -> Option<&T> {
+        nearest(self.root.as_deref(), query, distance)
+    }
+
+    /// The `k` values closest to `query` by `distance`, nearest first, in O(log n + k), like
+    /// `RBTree::k_nearest`.
+    pub fn k_nearest<D: PartialOrd, F: Fn(&T, &T) -> D>(&self, query: &T, k: usize, distance: F) -> Vec<&T> {
+        k_nearest(self.root.as_deref(), query, k, distance)
+    }
+
+    /// Number of nodes on the longest path from the root to a leaf.
+    pub fn height(&self) -> usize {
+        fn subtree_height<T, R>(node: Option<&Node<T, R>>) -> usize {
+            node.map_or(0, |node| 1 + subtree_height(node.left.as_deref()).max(subtree_height(node.right.as_deref())))
+        }
+        subtree_height(self.root.as_deref())
+    }
+
+    pub fn insert(&mut self, value: T) {
+        insert::<T, P>(&mut self.root, value);
+        if let Some(root) = self.root.as_mut() {
+            P::finish(root);
+        }
+    }
+
+    pub fn delete(&mut self, value: T) -> bool {
+        self.take(&value).is_some()
+    }
+
+    /// Removes a value equal to `value` and returns the one that was stored in the tree.
+    pub fn take(&mut self, mut value: &T) -> Option<T> {
+        self.take_target(&mut value)
+    }
+
+    fn take_target(&mut self, target: &mut impl Target<T>) -> Option<T> {
+        let (removed, _) = delete::<T, P>(&mut self.root, target);
+        if let Some(root) = self.root.as_mut() {
+            P::finish(root);
+        }
+        removed
+    }
+
+    /// Keeps only the values for which `f` returns true. The tree is rebuilt in O(n).
+    pub fn retain<F: FnMut(&T) -> bool>(&mut self, f: F) {
+        let kept: Vec<T> = self.drain().filter(f).collect();
+        let len = kept.len();
+        self.root = build::<T, P>(&mut kept.into_iter(), len, 0, red_depth(len));
+    }
+
+    /// Removes all values from the tree, yielding them in order.
+    pub fn drain(&mut self) -> IntoIter<T, P> {
+        let mut iter = IntoIter { stack: Vec::new() };
+        iter.push_left_spine(self.root.take());
+        iter
+    }
+
+    /// Returns an iterator that removes the values for which `pred` returns true, lazily like
+    /// `RBTree::extract_if`.
+    pub fn extract_if<F: FnMut(&T) -> bool>(&mut self, pred: F) -> ExtractIf<'_, Self, F> {
+        ExtractIf::new(self, pred)
+    }
+
+    /// Removes every value inside `range` and returns how many were removed, like
+    /// `RBTree::remove_range`.
+    pub fn remove_range<R: RangeBounds<T>>(&mut self, range: R) -> usize {
+        remove_range(self, range)
+    }
+
+    /// Checks the ordering, the subtree sizes and the balancing rules of the policy in O(n).
+    pub fn validate(&self) -> Result<(), InvariantViolation<'_, T>> {
+        match self.root.as_deref() {
+            Some(root) => {
+                validate_order(root, &mut Vec::new(), None, None)?;
+                validate_sizes(Some(root), &mut Vec::new())?;
+                P::validate(root)
+            },
+            None => Ok(()),
+        }
+    }
+}
+
+impl<T: Ord, P: Balance> Positional for BalancedTree<T, P> {
+    type Value = T;
+    type Node = Node<T, P::Rank>;
+
+    fn root(&self) -> Option<&Node<T, P::Rank>> {
+        self.root.as_deref()
+    }
+
+    fn take_at(&mut self, index: usize) -> Option<T> {
+        self.take_target(&mut Position(index))
+    }
+
+    fn retain<F: FnMut(&T) -> bool>(&mut self, f: F) {
+        BalancedTree::retain(self, f)
+    }
+}
+
+// Builds a subtree of `len` values taken from `values`, splitting them in the middle.
+fn build<T: Ord, P: Balance>(values: &mut impl Iterator<Item = T>, len: usize, depth: usize, red_depth: usize) -> Link<T, P::Rank> {
+    if len == 0 {
+        return None;
+    }
+    let left_len = (len - 1) / 2;
+    let left = build::<T, P>(values, left_len, depth + 1, red_depth);
+    let value = values.next().expect("There should be as many values as the length says");
+    let right = build::<T, P>(values, len - 1 - left_len, depth + 1, red_depth);
+    let height = (usize::BITS - 1 - len.leading_zeros()) as i32;
+    Some(Box::new(Node { value, rank: P::built(height, depth == red_depth), left, right, size: len }))
+}
+
+fn insert<T: Ord, P: Balance>(link: &mut Link<T, P::Rank>, value: T) -> bool {
+    let node = match link {
+        Some(node) => node,
+        None => {
+            *link = Some(Box::new(Node { value, rank: P::leaf(), left: None, right: None, size: 1 }));
+            return true;
+        },
+    };
+    // the new node always ends up below, rotations take the size over
+    node.size += 1;
+    let dir = if value < node.value { Direction::Left } else { Direction::Right };
+    if !insert::<T, P>(node.link(dir), value) {
+        return false;
+    }
+    let (node, changed) = P::inserted(link.take().unwrap(), dir);
+    *link = Some(node);
+    changed
+}
+
+// Returns the removed value and whether the parent has to look at the subtree again.
+fn delete<T: Ord, P: Balance>(link: &mut Link<T, P::Rank>, target: &mut impl Target<T>) -> (Option<T>, bool) {
+    let node = match link {
+        Some(node) => node,
+        None => return (None, false),
+    };
+    let (removed, dir, changed) = match target.locate(&node.value, size(node.left.as_deref())) {
+        Ordering::Less => {
+            let (removed, changed) = delete::<T, P>(&mut node.left, target);
+            (removed, Direction::Left, changed)
+        },
+        Ordering::Greater => {
+            let (removed, changed) = delete::<T, P>(&mut node.right, target);
+            (removed, Direction::Right, changed)
+        },
+        Ordering::Equal if node.left.is_some() && node.right.is_some() => {
+            // the successor takes the place of the value
+            let (successor, changed) = delete_min::<T, P>(&mut node.right);
+            (Some(core::mem::replace(&mut node.value, successor)), Direction::Right, changed)
+        },
+        Ordering::Equal => return remove::<T, P>(link),
+    };
+    if removed.is_some() {
+        node.size -= 1;
+    }
+    if !changed {
+        return (removed, false);
+    }
+    let (node, changed) = P::deleted(link.take().unwrap(), dir);
+    *link = Some(node);
+    (removed, changed)
+}
+
+fn delete_min<T: Ord, P: Balance>(link: &mut Link<T, P::Rank>) -> (T, bool) {
+    let node = link.as_mut().expect("The subtree to take the minimum from is not empty");
+    if node.left.is_none() {
+        let (removed, changed) = remove::<T, P>(link);
+        return (removed.unwrap(), changed);
+    }
+    let (min, changed) = delete_min::<T, P>(&mut node.left);
+    node.size -= 1;
+    if !changed {
+        return (min, false);
+    }
+    let (node, changed) = P::deleted(link.take().unwrap(), Direction::Left);
+    *link = Some(node);
+    (min, changed)
+}
+
+// Replaces the node in `link`, which has at most one child, with that child.
+fn remove<T: Ord, P: Balance>(link: &mut Link<T, P::Rank>) -> (Option<T>, bool) {
+    let node = *link.take().unwrap();
+    let (child, changed) = P::removed(node.rank, node.left.or(node.right));
+    *link = child;
+    (Some(node.value), changed)
+}
+
+impl<T: Ord, P: Balance> Default for BalancedTree<T, P> {
+    fn default() -> BalancedTree<T, P> {
+        BalancedTree::new()
+    }
+}
+
+impl<T: Ord, P: Balance> FromIterator<T> for BalancedTree<T, P> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        let mut tree = BalancedTree::new();
+        for value in iter {
+            tree.insert(value);
+        }
+        tree
+    }
+}
+
+impl<T: Ord + fmt::Debug, P: Balance> fmt::Debug for BalancedTree<T, P> {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.debug_set().entries(self.iter()).finish()
+    }
+}
+
+pub struct Iter<'a, T: Ord, P: Balance> {
+    stack: Vec<&'a Node<T, P::Rank>>,
+}
+
+impl<'a, T: Ord, P: Balance> Iter<'a, T, P> {
+    fn push_left_spine(&mut self, mut next: Option<&'a Node<T, P::Rank>>) {
+        while let Some(node) = next {
+            self.stack.push(node);
+            next = node.left.as_deref();
+        }
+    }
+}
+
+impl<'a, T: Ord, P: Balance> Iterator for Iter<'a, T, P> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let node = self.stack.pop()?;
+        self.push_left_spine(node.right.as_deref());
+        Some(&node.value)
+    }
+}
+
+pub struct Range<'a, T: Ord, P: Balance> {
+    iter: Iter<'a, T, P>,
+    remaining: usize,
+}
+
+impl<'a, T: Ord, P: Balance> Iterator for Range<'a, T, P> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.remaining == 0 {
+            return None;
+        }
+        self.remaining -= 1;
+        self.iter.next()
+    }
+}
+
+pub struct IntoIter<T: Ord, P: Balance> {
+    // nodes whose value and right subtree are still to come, the innermost last
+    stack: Vec<Box<Node<T, P::Rank>>>,
+}
+
+impl<T: Ord, P: Balance> IntoIter<T, P> {
+    fn push_left_spine(&mut self, mut next: Link<T, P::Rank>) {
+        while let Some(mut node) = next {
+            next = node.left.take();
+            self.stack.push(node);
+        }
+    }
+}
+
+impl<T: Ord, P: Balance> Iterator for IntoIter<T, P> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let node = *self.stack.pop()?;
+        self.push_left_spine(node.right);
+        Some(node.value)
+    }
+}
+
+impl<T: Ord, P: Balance> IntoIterator for BalancedTree<T, P> {
+    type Item = T;
+    type IntoIter = IntoIter<T, P>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        let mut iter = IntoIter { stack: Vec::new() };
+        iter.push_left_spine(self.root);
+        iter
+    }
+}
+
+impl<'a, T: Ord, P: Balance> IntoIterator for &'a BalancedTree<T, P> {
+    type Item = &'a T;
+    type IntoIter = Iter<'a, T, P>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use core::ops::Bound;
+
+    use super::*;
+
+    fn check_policy<P: Balance>() {
+        let values: Vec<i32> = (0..500).map(|i| (i * 193) % 211).collect();
+        let mut tree = BalancedTree::<i32, P>::new();
+        for value in values.iter() {
+            tree.insert(*value);
+            assert_eq!(tree.validate(), Ok(()));
+        }
+        let mut sorted = values.clone();
+        sorted.sort();
+        assert_eq!(tree.iter().copied().collect::<Vec<i32>>(), sorted);
+
+        assert!(!tree.delete(1000));
+        for value in values.iter().step_by(2) {
+            assert!(tree.delete(*value));
+            assert_eq!(tree.validate(), Ok(()));
+        }
+        for value in values.iter().skip(1).step_by(2) {
+            assert!(tree.contains(value));
+        }
+        assert_eq!(tree.len(), 250);
+        assert_eq!(tree.into_iter().count(), 250);
+    }
+
+    #[test]
+    fn test_red_black() {
+        check_policy::<RedBlack>();
+    }
+
+    #[test]
+    fn test_avl() {
+        check_policy::<Avl>();
+    }
+
+    #[test]
+    fn test_wavl() {
+        check_policy::<Wavl>();
+    }
+
+    #[test]
+    fn test_avl_height() {
+        let avl: BalancedTree<i32, Avl> = (0..1023).collect();
+        let red_black: BalancedTree<i32, RedBlack> = (0..1023).collect();
+
+        // sequential inserts leave AVL perfectly balanced, red-black only within twice the optimum
+        assert_eq!(avl.height(), 10);
+        assert!(red_black.height() > 10);
+    }
+
+    #[test]
+    fn test_range_and_order_statistics() {
+        let tree: BalancedTree<i32, Wavl> = (0..100).map(|i| i / 2).collect();
+
+        assert_eq!(tree.len(), 100);
+        assert_eq!(tree.range(10..12).copied().collect::<Vec<i32>>(), vec![10, 10, 11, 11]);
+        assert_eq!(tree.range((Bound::Excluded(47), Bound::Unbounded)).copied().collect::<Vec<i32>>(), vec![48, 48, 49, 49]);
+        assert_eq!(tree.range(..=0).count(), 2);
+        assert_eq!(tree.range(60..).count(), 0);
+        assert_eq!(tree.select(21), Some(&10));
+        assert_eq!(tree.select(100), None);
+        assert_eq!(tree.rank(&10), 20);
+    }
+
+    #[test]
+    fn test_take() {
+        let mut tree: BalancedTree<(i32, &str), Wavl> = vec![(2, "b"), (1, "a"), (3, "c")].into_iter().collect();
+
+        assert_eq!(tree.take(&(2, "b")), Some((2, "b")));
+        assert_eq!(tree.take(&(2, "b")), None);
+        assert_eq!(format!("{:?}", tree), r#"{(1, "a"), (3, "c")}"#);
+    }
+
+    #[test]
+    fn test_nearest() {
+        let tree: BalancedTree<i32, Avl> = vec![1, 5, 9, 12].into_iter().collect();
+        let distance = |a: &i32, b: &i32| (a - b).abs();
+
+        assert_eq!(tree.get(&9), Some(&9));
+        assert_eq!(tree.get(&8), None);
+        assert_eq!(tree.nearest(&7, distance), Some(&5));
+        assert_eq!(tree.k_nearest(&10, 3, distance), vec![&9, &12, &5]);
+    }
+
+    fn check_bulk<P: Balance>() {
+        let mut tree: BalancedTree<i32, P> = (0..100).collect();
+
+        let mut threes = tree.extract_if(|value| value % 3 == 0);
+        assert_eq!(threes.next(), Some(0));
+        assert_eq!(threes.next(), Some(3));
+        assert_eq!(tree.len(), 98);
+        assert_eq!(tree.validate(), Ok(()));
+
+        assert_eq!(tree.remove_range(10..20), 10);
+        assert_eq!(tree.remove_range(50..), 50);
+        assert_eq!(tree.validate(), Ok(()));
+
+        tree.retain(|value| value % 2 == 0);
+        assert_eq!(tree.validate(), Ok(()));
+        assert_eq!(tree.drain().collect::<Vec<i32>>(), vec![2, 4, 6, 8, 20, 22, 24, 26, 28, 30, 32, 34, 36, 38, 40, 42, 44, 46, 48]);
+        assert!(tree.is_empty());
+    }
+
+    #[test]
+    fn test_bulk() {
+        check_bulk::<RedBlack>();
+        check_bulk::<Avl>();
+        check_bulk::<Wavl>();
+    }
+}
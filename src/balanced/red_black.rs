@@ -0,0 +1,125 @@
+use super::policy::Policy;
+use super::*;
+use crate::tree::{validate_colored, Color, ColoredNode};
+
+/// Red-black rules, the same as `RBTree`'s: no red node has a red child, and all paths from a
+/// node down to its leaves pass the same number of black nodes.
+pub struct RedBlack;
+
+fn is_red<T>(node: Option<&Node<T, Color>>) -> bool {
+    node.is_some_and(|node| node.rank == Color::Red)
+}
+
+fn child_mut<T>(node: &mut Node<T, Color>, dir: Direction) -> &mut Node<T, Color> {
+    node.link(dir).as_mut().expect("The recolored nodes exist")
+}
+
+// After an insert, `changed` means the root of the subtree is red, so its parent has to check
+// for two reds in a row. After a delete, it means the subtree lost a black node on every path.
+impl Policy for RedBlack {
+    type Rank = Color;
+
+    fn leaf() -> Color {
+        Color::Red
+    }
+
+    fn inserted<T>(mut node: Box<Node<T, Color>>, dir: Direction) -> (Box<Node<T, Color>>, bool) {
+        let child = node.child(dir).unwrap();
+        let red_grandchild = [Direction::Left, Direction::Right].iter().copied().find(|grandchild_dir| is_red(child.child(*grandchild_dir)));
+        let grandchild_dir = match red_grandchild {
+            Some(grandchild_dir) if child.rank == Color::Red => grandchild_dir,
+            _ => {
+                let is_red = node.rank == Color::Red;
+                return (node, is_red);
+            },
+        };
+
+        if is_red(node.child(dir.opposite())) {
+            // red uncle: the red moves up to this node
+            node.rank = Color::Red;
+            child_mut(&mut node, Direction::Left).rank = Color::Black;
+            child_mut(&mut node, Direction::Right).rank = Color::Black;
+            return (node, true);
+        }
+        if grandchild_dir != dir {
+            let child = node.link(dir).take().unwrap();
+            *node.link(dir) = Some(rotate(child, dir));
+        }
+        let mut top = rotate(node, dir.opposite());
+        top.rank = Color::Black;
+        child_mut(&mut top, dir.opposite()).rank = Color::Red;
+        (top, false)
+    }
+
+    fn removed<T>(rank: Color, child: Link<T, Color>) -> (Link<T, Color>, bool) {
+        match (rank, child) {
+            (Color::Red, child) => (child, false),
+            (Color::Black, Some(mut child)) if child.rank == Color::Red => {
+                child.rank = Color::Black;
+                (Some(child), false)
+            },
+            (Color::Black, child) => (child, true),
+        }
+    }
+
+    fn deleted<T>(mut node: Box<Node<T, Color>>, dir: Direction) -> (Box<Node<T, Color>>, bool) {
+        let sibling = node.child(dir.opposite()).expect("A subtree that lost a black node has a sibling");
+        if sibling.rank == Color::Red {
+            // rotating the red sibling up gives a black sibling below a red parent
+            node.rank = Color::Red;
+            let mut top = rotate(node, dir);
+            top.rank = Color::Black;
+            let lowered = top.link(dir).take().unwrap();
+            let (fixed, _) = Self::deleted(lowered, dir);
+            *top.link(dir) = Some(fixed);
+            return (top, false);
+        }
+
+        let close_is_red = is_red(sibling.child(dir));
+        let distant_is_red = is_red(sibling.child(dir.opposite()));
+        if !close_is_red && !distant_is_red {
+            child_mut(&mut node, dir.opposite()).rank = Color::Red;
+            if node.rank == Color::Red {
+                node.rank = Color::Black;
+                return (node, false);
+            }
+            return (node, true);
+        }
+        if !distant_is_red {
+            // the close nephew becomes the sibling, with the old sibling as red distant nephew
+            let sibling = node.link(dir.opposite()).take().unwrap();
+            let mut rotated = rotate(sibling, dir.opposite());
+            rotated.rank = Color::Black;
+            child_mut(&mut rotated, dir.opposite()).rank = Color::Red;
+            *node.link(dir.opposite()) = Some(rotated);
+        }
+        let color = node.rank;
+        let mut top = rotate(node, dir);
+        top.rank = color;
+        child_mut(&mut top, dir).rank = Color::Black;
+        child_mut(&mut top, dir.opposite()).rank = Color::Black;
+        (top, false)
+    }
+
+    fn finish<T>(root: &mut Node<T, Color>) {
+        root.rank = Color::Black;
+    }
+
+    // like `RBTree`'s bulk build, the lowest level is red if it is not full, which keeps the
+    // black height the same on every path
+    fn built(_height: i32, lowest: bool) -> Color {
+        if lowest { Color::Red } else { Color::Black }
+    }
+
+    fn validate<T: Ord>(root: &Node<T, Color>) -> Result<(), InvariantViolation<'_, T>> {
+        validate_colored(Some(root))
+    }
+}
+
+impl Balance for RedBlack {}
+
+impl<T: Ord> ColoredNode for Node<T, Color> {
+    fn color(&self) -> Color {
+        self.rank
+    }
+}
@@ -0,0 +1,102 @@
+use super::policy::Policy;
+use super::*;
+
+/// Weak AVL rules, from Haeupler, Sen and Tarjan, "Rank-Balanced Trees" (2015): every child has
+/// a rank one or two below its parent's, missing children count as rank -1, and leaves have rank 0.
+pub struct Wavl;
+
+fn child_mut<T>(node: &mut Node<T, i32>, dir: Direction) -> &mut Node<T, i32> {
+    node.link(dir).as_mut().expect("The rotated nodes are in place")
+}
+
+// After an insert, `changed` means the child may have the same rank as its parent. After a
+// delete, it means the child may be three ranks below its parent.
+impl Policy for Wavl {
+    type Rank = i32;
+
+    fn leaf() -> i32 {
+        0
+    }
+
+    fn inserted<T>(mut node: Box<Node<T, i32>>, dir: Direction) -> (Box<Node<T, i32>>, bool) {
+        let child = node.child(dir).unwrap();
+        if child.rank != node.rank {
+            return (node, false);
+        }
+        if node.rank - rank(node.child(dir.opposite())) == 1 {
+            node.rank += 1;
+            return (node, true);
+        }
+        // the child was just promoted, so its ranks differ by 1 and 2
+        if child.rank - rank(child.child(dir.opposite())) == 2 {
+            let mut top = rotate(node, dir.opposite());
+            child_mut(&mut top, dir.opposite()).rank -= 1;
+            (top, false)
+        } else {
+            let child = node.link(dir).take().unwrap();
+            *node.link(dir) = Some(rotate(child, dir));
+            let mut top = rotate(node, dir.opposite());
+            top.rank += 1;
+            child_mut(&mut top, dir).rank -= 1;
+            child_mut(&mut top, dir.opposite()).rank -= 1;
+            (top, false)
+        }
+    }
+
+    fn removed<T>(_rank: i32, child: Link<T, i32>) -> (Link<T, i32>, bool) {
+        (child, true)
+    }
+
+    fn deleted<T>(mut node: Box<Node<T, i32>>, dir: Direction) -> (Box<Node<T, i32>>, bool) {
+        if node.is_leaf() && node.rank == 1 {
+            node.rank = 0;
+            return (node, true);
+        }
+        if node.rank - rank(node.child(dir)) < 3 {
+            return (node, false);
+        }
+        let sibling = node.child(dir.opposite()).expect("A child three ranks down has a sibling");
+        if node.rank - sibling.rank == 2 {
+            node.rank -= 1;
+            return (node, true);
+        }
+        let inner = sibling.rank - rank(sibling.child(dir));
+        let outer = sibling.rank - rank(sibling.child(dir.opposite()));
+        if inner == 2 && outer == 2 {
+            child_mut(&mut node, dir.opposite()).rank -= 1;
+            node.rank -= 1;
+            return (node, true);
+        }
+        if outer == 1 {
+            let mut top = rotate(node, dir);
+            top.rank += 1;
+            let lowered = child_mut(&mut top, dir);
+            lowered.rank -= 1;
+            if lowered.is_leaf() {
+                lowered.rank -= 1;
+            }
+            (top, false)
+        } else {
+            let sibling = node.link(dir.opposite()).take().unwrap();
+            *node.link(dir.opposite()) = Some(rotate(sibling, dir.opposite()));
+            let mut top = rotate(node, dir);
+            top.rank += 2;
+            child_mut(&mut top, dir).rank -= 2;
+            child_mut(&mut top, dir.opposite()).rank -= 1;
+            (top, false)
+        }
+    }
+
+    // the built tree is an AVL tree, and with ranks equal to the heights it is a valid WAVL tree
+    fn built(height: i32, _lowest: bool) -> i32 {
+        height
+    }
+
+    fn validate<T: Ord>(root: &Node<T, i32>) -> Result<(), InvariantViolation<'_, T>> {
+        check_rank_differences(root, &mut Vec::new(), |left, right, leaf| {
+            (1..=2).contains(&left) && (1..=2).contains(&right) && !(leaf && left == 2)
+        })
+    }
+}
+
+impl Balance for Wavl {}
@@ -0,0 +1,128 @@
+use std::sync::{Arc, Mutex, PoisonError};
+
+use arc_swap::ArcSwap;
+
+use crate::persistent::PersistentRBTree;
+
+/// A tree that can be shared between threads. Readers work on O(1) snapshots and never block:
+/// writers build the next version on the side and then swap it in atomically. Writers are
+/// serialized with each other.
+pub struct ConcurrentRBTree<T: Ord + Clone> {
+    current: ArcSwap<PersistentRBTree<T>>,
+    writer: Mutex<()>,
+}
+
+impl<T: Ord + Clone> Default for ConcurrentRBTree<T> {
+    fn default() -> Self {
+        ConcurrentRBTree::new()
+    }
+}
+
+impl<T: Ord + Clone> ConcurrentRBTree<T> {
+    pub fn new() -> ConcurrentRBTree<T> {
+        ConcurrentRBTree { current: ArcSwap::from_pointee(PersistentRBTree::new()), writer: Mutex::new(()) }
+    }
+
+    /// The latest committed version. Later writes don't affect it.
+    pub fn snapshot(&self) -> PersistentRBTree<T> {
+        PersistentRBTree::clone(&self.current.load())
+    }
+
+    pub fn contains(&self, value: T) -> bool {
+        self.snapshot().contains(value)
+    }
+
+    pub fn insert(&self, value: T) {
+        self.update(|tree| tree.insert(value));
+    }
+
+    /// Returns whether the value was in the tree.
+    pub fn delete(&self, value: T) -> bool {
+        let mut found = false;
+        self.update(|tree| {
+            let next = tree.delete(value);
+            found = !next.ptr_eq(tree);
+            next
+        });
+        found
+    }
+
+    /// Builds the next version from the latest one and commits it as a whole, so readers see
+    /// either none or all of the changes. Returns the committed version.
+    pub fn update<F: FnOnce(&PersistentRBTree<T>) -> PersistentRBTree<T>>(&self, f: F) -> PersistentRBTree<T> {
+        // a writer that panicked never swapped in a half-built version, so poisoning is ignored
+        let _writer = self.writer.lock().unwrap_or_else(PoisonError::into_inner);
+        let next = f(&self.snapshot());
+        // readers still holding the previous version keep its nodes alive until they let go
+        self.current.store(Arc::new(next.clone()));
+        next
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::thread;
+
+    #[test]
+    fn test_single_thread() {
+        let tree = ConcurrentRBTree::new();
+        tree.insert(3);
+        tree.insert(1);
+        let snapshot = tree.snapshot();
+        tree.insert(2);
+
+        assert!(tree.delete(1));
+        assert!(!tree.delete(10));
+        assert_eq!(snapshot.iter().copied().collect::<Vec<i32>>(), vec![1, 3]);
+        assert_eq!(tree.snapshot().iter().copied().collect::<Vec<i32>>(), vec![2, 3]);
+    }
+
+    #[test]
+    fn test_readers_see_consistent_snapshots() {
+        let tree = ConcurrentRBTree::<i64>::new();
+        let done = AtomicBool::new(false);
+
+        thread::scope(|scope| {
+            for _ in 0..4 {
+                scope.spawn(|| {
+                    while !done.load(Ordering::Relaxed) {
+                        let snapshot = tree.snapshot();
+                        assert_eq!(snapshot.validate(), Ok(()));
+                        // every commit inserts and deletes a value and its negation together
+                        let sum: i64 = snapshot.iter().sum();
+                        assert_eq!(sum, 0);
+                        let values: Vec<&i64> = snapshot.iter().collect();
+                        assert!(values.windows(2).all(|pair| pair[0] <= pair[1]));
+                    }
+                });
+            }
+
+            let writers: Vec<_> = (0..2i64)
+                .map(|writer| {
+                    let tree = &tree;
+                    scope.spawn(move || {
+                        for i in 1..=500i64 {
+                            let value = i * 2 + writer;
+                            let committed = tree.update(|current| current.insert(value).insert(-value));
+                            assert_eq!(committed.validate(), Ok(()));
+                            if i % 3 == 0 {
+                                let committed = tree.update(|current| current.delete(value).delete(-value));
+                                assert_eq!(committed.validate(), Ok(()));
+                            }
+                        }
+                    })
+                })
+                .collect();
+            for writer in writers {
+                writer.join().unwrap();
+            }
+            done.store(true, Ordering::Relaxed);
+        });
+
+        let snapshot = tree.snapshot();
+        assert_eq!(snapshot.validate(), Ok(()));
+        assert_eq!(snapshot.iter().count(), 2 * 2 * (500 - 166));
+    }
+}
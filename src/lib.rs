@@ -1 +1,25 @@
-pub mod tree;
\ No newline at end of file
+#![cfg_attr(not(any(feature = "std", test)), no_std)]
+
+extern crate alloc;
+
+// Every module is public, and the root re-exports the crate's types most users need.
+pub mod balanced;
+#[cfg(feature = "std")]
+pub mod concurrent;
+pub mod persistent;
+pub mod rolling;
+pub mod tree;
+
+#[cfg(feature = "std")]
+pub use concurrent::ConcurrentRBTree;
+pub use balanced::BalancedTree;
+pub use persistent::PersistentRBTree;
+pub use rolling::{RollingQuantile, TotalF64};
+pub use tree::{
+    Color, Direction, Event, ExtractIf, FixupCase, IntoIter, InvariantViolation, Iter, LLRBTree, NodeRef, Pretty, RBTree, RBTreeMap, RotationKind, TraceEvent, Transaction,
+    TreeStats, UpdateMode,
+};
+#[cfg(feature = "metrics")]
+pub use tree::Metrics;
+#[cfg(feature = "rayon")]
+pub use tree::{IntoParIter, ParIter};
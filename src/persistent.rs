@@ -0,0 +1,411 @@
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::cmp::Ordering;
+
+use crate::tree::{validate_colored, Color, ColoredNode, Direction, InvariantViolation, SearchNode};
+
+type Link<T> = Option<Arc<PersistentNode<T>>>;
+
+struct PersistentNode<T> {
+    color: Color,
+    value: T,
+    left: Link<T>,
+    right: Link<T>,
+}
+
+/// An immutable red-black tree. Every update returns a new version that shares all untouched
+/// subtrees with the old one, so cloning a version is O(1) and old versions stay valid.
+///
+/// The balancing follows Kahrs, "Red-black trees with types" (2001).
+pub struct PersistentRBTree<T: Ord + Clone> {
+    root: Link<T>,
+}
+
+impl<T: Ord + Clone> Clone for PersistentRBTree<T> {
+    fn clone(&self) -> Self {
+        PersistentRBTree { root: self.root.clone() }
+    }
+}
+
+impl<T: Ord + Clone> Default for PersistentRBTree<T> {
+    fn default() -> Self {
+        PersistentRBTree::new()
+    }
+}
+
+impl<T: Ord + Clone> PersistentRBTree<T> {
+    pub fn new() -> PersistentRBTree<T> {
+        PersistentRBTree { root: None }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.root.is_none()
+    }
+
+    pub fn contains(&self, value: T) -> bool {
+        find(&self.root, &value)
+    }
+
+    /// Returns a new version with `value` added. Equal values are kept side by side.
+    pub fn insert(&self, value: T) -> PersistentRBTree<T> {
+        PersistentRBTree { root: blacken(insert(&self.root, &value)) }
+    }
+
+    /// Returns a new version with one occurrence of `value` removed.
+    /// If the value is not in the tree, the new version is a clone of this one.
+    pub fn delete(&self, value: T) -> PersistentRBTree<T> {
+        if !find(&self.root, &value) {
+            return self.clone();
+        }
+        PersistentRBTree { root: blacken(delete(&self.root, &value)) }
+    }
+
+    /// Whether both versions are the same tree in memory, which is how unchanged versions can be spotted in O(1).
+    pub fn ptr_eq(&self, other: &PersistentRBTree<T>) -> bool {
+        match (&self.root, &other.root) {
+            (Some(root), Some(other_root)) => Arc::ptr_eq(root, other_root),
+            (None, None) => true,
+            _ => false,
+        }
+    }
+
+    pub fn iter(&self) -> Iter<'_, T> {
+        let mut iter = Iter { stack: Vec::new() };
+        iter.push_left_spine(self.root.as_deref());
+        iter
+    }
+
+    /// Checks the red-black and ordering invariants of this version in O(n).
+    pub fn validate(&self) -> Result<(), InvariantViolation<'_, T>> {
+        validate_colored(self.root.as_deref())
+    }
+}
+
+impl<'a, T: Ord + Clone> IntoIterator for &'a PersistentRBTree<T> {
+    type Item = &'a T;
+    type IntoIter = Iter<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+pub struct Iter<'a, T> {
+    stack: Vec<&'a PersistentNode<T>>,
+}
+
+impl<'a, T> Iter<'a, T> {
+    fn push_left_spine(&mut self, mut next: Option<&'a PersistentNode<T>>) {
+        while let Some(node) = next {
+            self.stack.push(node);
+            next = node.left.as_deref();
+        }
+    }
+}
+
+impl<'a, T> Iterator for Iter<'a, T> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let node = self.stack.pop()?;
+        self.push_left_spine(node.right.as_deref());
+        Some(&node.value)
+    }
+}
+
+impl<T: Ord> SearchNode for PersistentNode<T> {
+    type Value = T;
+
+    fn value(&self) -> &T {
+        &self.value
+    }
+
+    fn child(&self, dir: Direction) -> Option<&Self> {
+        match dir {
+            Direction::Left => self.left.as_deref(),
+            Direction::Right => self.right.as_deref(),
+        }
+    }
+}
+
+impl<T: Ord> ColoredNode for PersistentNode<T> {
+    fn color(&self) -> Color {
+        self.color
+    }
+}
+
+fn node<T>(color: Color, left: Link<T>, value: T, right: Link<T>) -> Link<T> {
+    Some(Arc::new(PersistentNode { color, value, left, right }))
+}
+
+fn red<T>(link: &Link<T>) -> Option<&PersistentNode<T>> {
+    link.as_deref().filter(|node| node.color == Color::Red)
+}
+
+fn black<T>(link: &Link<T>) -> Option<&PersistentNode<T>> {
+    link.as_deref().filter(|node| node.color == Color::Black)
+}
+
+fn recolored<T: Clone>(node: &PersistentNode<T>, color: Color) -> Link<T> {
+    self::node(color, node.left.clone(), node.value.clone(), node.right.clone())
+}
+
+fn blacken<T: Clone>(link: Link<T>) -> Link<T> {
+    match red(&link) {
+        Some(root) => recolored(root, Color::Black),
+        None => link,
+    }
+}
+
+fn find<T: Ord>(link: &Link<T>, value: &T) -> bool {
+    let mut next = link.as_deref();
+    while let Some(node) = next {
+        match value.cmp(&node.value) {
+            Ordering::Equal => return true,
+            Ordering::Less => next = node.left.as_deref(),
+            Ordering::Greater => next = node.right.as_deref(),
+        }
+    }
+    false
+}
+
+// Resolves a red node with a red child below a black node, or two red children.
+fn balance<T: Clone>(left: Link<T>, value: T, right: Link<T>) -> Link<T> {
+    if let (Some(l), Some(r)) = (red(&left), red(&right)) {
+        return node(Color::Red, recolored(l, Color::Black), value, recolored(r, Color::Black));
+    }
+    if let Some(l) = red(&left) {
+        if let Some(ll) = red(&l.left) {
+            return node(Color::Red, recolored(ll, Color::Black), l.value.clone(), node(Color::Black, l.right.clone(), value, right));
+        }
+        if let Some(lr) = red(&l.right) {
+            return node(
+                Color::Red,
+                node(Color::Black, l.left.clone(), l.value.clone(), lr.left.clone()),
+                lr.value.clone(),
+                node(Color::Black, lr.right.clone(), value, right),
+            );
+        }
+    }
+    if let Some(r) = red(&right) {
+        if let Some(rr) = red(&r.right) {
+            return node(Color::Red, node(Color::Black, left, value, r.left.clone()), r.value.clone(), recolored(rr, Color::Black));
+        }
+        if let Some(rl) = red(&r.left) {
+            return node(
+                Color::Red,
+                node(Color::Black, left, value, rl.left.clone()),
+                rl.value.clone(),
+                node(Color::Black, rl.right.clone(), r.value.clone(), r.right.clone()),
+            );
+        }
+    }
+    node(Color::Black, left, value, right)
+}
+
+fn insert<T: Ord + Clone>(link: &Link<T>, value: &T) -> Link<T> {
+    let current = match link.as_deref() {
+        Some(current) => current,
+        None => return node(Color::Red, None, value.clone(), None),
+    };
+    let go_left = *value < current.value;
+    match (current.color, go_left) {
+        (Color::Black, true) => balance(insert(&current.left, value), current.value.clone(), current.right.clone()),
+        (Color::Black, false) => balance(current.left.clone(), current.value.clone(), insert(&current.right, value)),
+        (Color::Red, true) => node(Color::Red, insert(&current.left, value), current.value.clone(), current.right.clone()),
+        (Color::Red, false) => node(Color::Red, current.left.clone(), current.value.clone(), insert(&current.right, value)),
+    }
+}
+
+// The value must be in the tree. Deleting from a black subtree lowers its black height by one,
+// the callers make up for it with `rebalance_left` and `rebalance_right`.
+fn delete<T: Ord + Clone>(link: &Link<T>, value: &T) -> Link<T> {
+    let current = link.as_deref().expect("The deleted value should be in the tree");
+    match value.cmp(&current.value) {
+        Ordering::Less => {
+            if black(&current.left).is_some() {
+                rebalance_left(delete(&current.left, value), current.value.clone(), current.right.clone())
+            } else {
+                node(Color::Red, delete(&current.left, value), current.value.clone(), current.right.clone())
+            }
+        },
+        Ordering::Greater => {
+            if black(&current.right).is_some() {
+                rebalance_right(current.left.clone(), current.value.clone(), delete(&current.right, value))
+            } else {
+                node(Color::Red, current.left.clone(), current.value.clone(), delete(&current.right, value))
+            }
+        },
+        Ordering::Equal => join(&current.left, &current.right),
+    }
+}
+
+// The left subtree is one black node shorter than the right one.
+fn rebalance_left<T: Clone>(left: Link<T>, value: T, right: Link<T>) -> Link<T> {
+    if let Some(l) = red(&left) {
+        return node(Color::Red, recolored(l, Color::Black), value, right);
+    }
+    if let Some(r) = black(&right) {
+        return balance(left, value, recolored(r, Color::Red));
+    }
+    let r = red(&right).expect("Broken invariant: the taller sibling of a shortened subtree can not be a leaf");
+    let rl = black(&r.left).expect("Broken invariant: a red node must have black children of the same black height");
+    node(
+        Color::Red,
+        node(Color::Black, left, value, rl.left.clone()),
+        rl.value.clone(),
+        balance(rl.right.clone(), r.value.clone(), redden(&r.right)),
+    )
+}
+
+// The right subtree is one black node shorter than the left one.
+fn rebalance_right<T: Clone>(left: Link<T>, value: T, right: Link<T>) -> Link<T> {
+    if let Some(r) = red(&right) {
+        return node(Color::Red, left, value, recolored(r, Color::Black));
+    }
+    if let Some(l) = black(&left) {
+        return balance(recolored(l, Color::Red), value, right);
+    }
+    let l = red(&left).expect("Broken invariant: the taller sibling of a shortened subtree can not be a leaf");
+    let lr = black(&l.right).expect("Broken invariant: a red node must have black children of the same black height");
+    node(
+        Color::Red,
+        balance(redden(&l.left), l.value.clone(), lr.left.clone()),
+        lr.value.clone(),
+        node(Color::Black, lr.right.clone(), value, right),
+    )
+}
+
+fn redden<T: Clone>(link: &Link<T>) -> Link<T> {
+    let node = black(link).expect("Broken invariant: only black nodes can be turned red here");
+    recolored(node, Color::Red)
+}
+
+// Joins the two subtrees of a deleted node, everything in `left` is ordered before `right`.
+fn join<T: Clone>(left: &Link<T>, right: &Link<T>) -> Link<T> {
+    let (l, r) = match (left.as_deref(), right.as_deref()) {
+        (None, _) => return right.clone(),
+        (_, None) => return left.clone(),
+        (Some(l), Some(r)) => (l, r),
+    };
+    match (l.color, r.color) {
+        (Color::Red, Color::Red) => {
+            let middle = join(&l.right, &r.left);
+            match red(&middle) {
+                Some(m) => node(
+                    Color::Red,
+                    node(Color::Red, l.left.clone(), l.value.clone(), m.left.clone()),
+                    m.value.clone(),
+                    node(Color::Red, m.right.clone(), r.value.clone(), r.right.clone()),
+                ),
+                None => node(Color::Red, l.left.clone(), l.value.clone(), node(Color::Red, middle, r.value.clone(), r.right.clone())),
+            }
+        },
+        (Color::Black, Color::Black) => {
+            let middle = join(&l.right, &r.left);
+            match red(&middle) {
+                Some(m) => node(
+                    Color::Red,
+                    node(Color::Black, l.left.clone(), l.value.clone(), m.left.clone()),
+                    m.value.clone(),
+                    node(Color::Black, m.right.clone(), r.value.clone(), r.right.clone()),
+                ),
+                None => rebalance_left(l.left.clone(), l.value.clone(), node(Color::Black, middle, r.value.clone(), r.right.clone())),
+            }
+        },
+        (Color::Black, Color::Red) => node(Color::Red, join(left, &r.left), r.value.clone(), r.right.clone()),
+        (Color::Red, Color::Black) => node(Color::Red, l.left.clone(), l.value.clone(), join(&l.right, right)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_insert_and_contains() {
+        let mut tree = PersistentRBTree::new();
+        for i in [45, 13, 54, 14, 77, 12, 0, -3, 43, 111, 124, 55, 3, 1, 211434, 3] {
+            tree = tree.insert(i);
+            assert_eq!(tree.validate(), Ok(()));
+        }
+
+        assert!(tree.contains(111));
+        assert!(!tree.contains(2));
+        assert_eq!(tree.iter().filter(|value| **value == 3).count(), 2);
+        assert_eq!(tree.iter().count(), 16);
+    }
+
+    #[test]
+    fn test_old_versions_are_unchanged() {
+        let mut versions = vec![PersistentRBTree::new()];
+        for i in 0..200 {
+            let next = versions.last().unwrap().insert((i * 7) % 101);
+            versions.push(next);
+        }
+        for i in 0..200 {
+            let next = versions.last().unwrap().delete((i * 3) % 101);
+            versions.push(next);
+        }
+
+        let mut expected = Vec::new();
+        for (i, version) in versions.iter().enumerate().take(201) {
+            assert_eq!(version.validate(), Ok(()));
+            assert_eq!(version.iter().copied().collect::<Vec<i32>>(), expected);
+            if i < 200 {
+                expected.push((i as i32 * 7) % 101);
+                expected.sort();
+            }
+        }
+        for (i, version) in versions.iter().enumerate().skip(201) {
+            assert_eq!(version.validate(), Ok(()));
+            let removed = ((i - 201) as i32 * 3) % 101;
+            if let Ok(position) = expected.binary_search(&removed) {
+                expected.remove(position);
+            }
+            assert_eq!(version.iter().copied().collect::<Vec<i32>>(), expected);
+        }
+    }
+
+    #[test]
+    fn test_delete_missing_value_shares_the_tree() {
+        let tree = PersistentRBTree::new().insert(1).insert(2);
+        let same = tree.delete(3);
+        assert!(same.ptr_eq(&tree));
+        assert!(!tree.delete(2).ptr_eq(&tree));
+    }
+
+    #[test]
+    fn test_untouched_subtrees_are_shared() {
+        let mut tree = PersistentRBTree::new();
+        for i in 0..1000 {
+            tree = tree.insert(i);
+        }
+        let snapshot = tree.clone();
+        let updated = tree.insert(1000);
+
+        let shared_left = match (&snapshot.root, &updated.root) {
+            (Some(old), Some(new)) => match (&old.left, &new.left) {
+                (Some(old_left), Some(new_left)) => Arc::ptr_eq(old_left, new_left),
+                _ => false,
+            },
+            _ => false,
+        };
+        assert!(shared_left);
+        assert_eq!(snapshot.iter().count(), 1000);
+        assert_eq!(updated.iter().count(), 1001);
+    }
+
+    #[test]
+    fn test_delete_everything() {
+        let mut tree = PersistentRBTree::new();
+        for i in 0..300 {
+            tree = tree.insert(i % 50);
+        }
+        for i in 0..300 {
+            tree = tree.delete((i * 11) % 50);
+            assert_eq!(tree.validate(), Ok(()));
+        }
+        assert!(tree.is_empty());
+    }
+}
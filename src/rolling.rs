@@ -0,0 +1,181 @@
+use core::cmp::Ordering;
+
+use crate::RBTree;
+
+// Relative to the position, well above the rounding error of q * len and below any real fraction of a rank.
+const POSITION_EPSILON: f64 = 1e-12;
+
+/// Quantiles over a sliding window of samples, like a running median or p99 of the last N
+/// measurements. The window itself is up to the caller, usually a ring buffer: push every new
+/// sample and evict the one that drops out. Pushes, evictions and quantiles are O(log n).
+///
+/// Samples only need `Ord`, `f64` samples go through `TotalF64`.
+pub struct RollingQuantile<T: Ord> {
+    samples: RBTree<T>,
+}
+
+impl<T: Ord> Default for RollingQuantile<T> {
+    fn default() -> Self {
+        RollingQuantile::new()
+    }
+}
+
+impl<T: Ord> RollingQuantile<T> {
+    pub fn new() -> RollingQuantile<T> {
+        RollingQuantile { samples: RBTree::new() }
+    }
+
+    pub fn len(&self) -> usize {
+        self.samples.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.samples.is_empty()
+    }
+
+    pub fn push(&mut self, sample: T) {
+        self.samples.insert(sample);
+    }
+
+    /// Removes one copy of `old` from the window. Returns whether there was one.
+    pub fn evict(&mut self, old: &T) -> bool {
+        self.samples.take(old).is_some()
+    }
+
+    /// The sample at quantile `q` by the nearest-rank method: the smallest sample that is
+    /// greater than or equal to at least a `q` fraction of the window. `quantile(0.0)` is the
+    /// minimum, `quantile(1.0)` the maximum and for an even number of samples `quantile(0.5)`
+    /// is the lower of the two middle ones.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `q` is not between 0 and 1.
+    pub fn quantile(&self, q: f64) -> Option<&T> {
+        assert!((0.0..=1.0).contains(&q), "quantile needs a q between 0 and 1, got {}", q);
+        // the rank is ceil(q * len), counting from 1, which core can't compute for floats. A
+        // position within rounding error of a whole number is that number, or 0.07 * 100 would
+        // round up to rank 8.
+        let position = q * self.len() as f64;
+        let mut rank = position as usize;
+        if position - rank as f64 > POSITION_EPSILON * position.max(1.0) {
+            rank += 1;
+        }
+        self.samples.select(rank.max(1) - 1)
+    }
+
+    pub fn median(&self) -> Option<&T> {
+        self.quantile(0.5)
+    }
+}
+
+/// An `f64` ordered by `f64::total_cmp`, so it can be a sample of a `RollingQuantile` or a
+/// value in a tree. -0.0 sorts before 0.0 and NaNs sort after infinity, or before negative
+/// infinity if their sign bit is set.
+#[derive(Clone, Copy, Debug)]
+pub struct TotalF64(pub f64);
+
+impl From<f64> for TotalF64 {
+    fn from(value: f64) -> Self {
+        TotalF64(value)
+    }
+}
+
+impl PartialEq for TotalF64 {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl Eq for TotalF64 {}
+
+impl PartialOrd for TotalF64 {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for TotalF64 {
+    fn cmp(&self, other: &Self) -> Ordering {
+        self.0.total_cmp(&other.0)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::collections::VecDeque;
+
+    #[test]
+    fn test_running_median() {
+        let samples = [5, 1, 4, 4, 9, 2, 8, 4, 7, 3];
+        let mut window = VecDeque::new();
+        let mut rolling = RollingQuantile::new();
+        let mut medians = Vec::new();
+        for sample in samples.iter() {
+            window.push_back(*sample);
+            rolling.push(*sample);
+            if window.len() > 4 {
+                assert!(rolling.evict(&window.pop_front().unwrap()));
+            }
+            medians.push(*rolling.median().unwrap());
+        }
+
+        assert_eq!(medians, vec![5, 1, 4, 4, 4, 4, 4, 4, 4, 4]);
+        assert_eq!(rolling.len(), 4);
+        assert!(!rolling.evict(&100));
+    }
+
+    #[test]
+    fn test_quantiles_with_duplicates() {
+        let mut rolling = RollingQuantile::new();
+        assert_eq!(rolling.quantile(0.5), None);
+        for sample in (1..=100).chain(vec![50; 100]) {
+            rolling.push(sample);
+        }
+
+        assert_eq!(rolling.quantile(0.0), Some(&1));
+        assert_eq!(rolling.quantile(0.25), Some(&50));
+        assert_eq!(rolling.quantile(0.75), Some(&50));
+        assert_eq!(rolling.quantile(0.99), Some(&98));
+        assert_eq!(rolling.quantile(1.0), Some(&100));
+
+        for _ in 0..100 {
+            assert!(rolling.evict(&50));
+        }
+        assert!(rolling.evict(&50));
+        assert!(!rolling.evict(&50));
+        assert_eq!(rolling.median(), Some(&51));
+    }
+
+    #[test]
+    fn test_whole_percentiles() {
+        let samples: Vec<i32> = (0..100).map(|i| (i * 37) % 100).collect();
+        let mut rolling = RollingQuantile::new();
+        for sample in samples.iter() {
+            rolling.push(*sample);
+        }
+        let mut sorted = samples.clone();
+        sorted.sort();
+
+        for percentile in 1..=100 {
+            let q = percentile as f64 / 100.0;
+            assert_eq!(rolling.quantile(q), Some(&sorted[percentile - 1]), "p{}", percentile);
+        }
+        assert_eq!(rolling.quantile(0.0), Some(&sorted[0]));
+    }
+
+    #[test]
+    fn test_f64_samples() {
+        let mut rolling = RollingQuantile::new();
+        for sample in [0.5, -0.0, f64::NAN, 0.0, f64::NEG_INFINITY, 2.5].iter() {
+            rolling.push(TotalF64(*sample));
+        }
+
+        assert_eq!(rolling.quantile(0.0).unwrap().0, f64::NEG_INFINITY);
+        assert!(rolling.quantile(0.3).unwrap().0.is_sign_negative());
+        assert!(rolling.median().unwrap().0.is_sign_positive());
+        assert!(rolling.quantile(1.0).unwrap().0.is_nan());
+        assert!(rolling.evict(&TotalF64(f64::NAN)));
+        assert_eq!(rolling.quantile(1.0).unwrap().0, 2.5);
+    }
+}
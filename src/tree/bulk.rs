@@ -0,0 +1,179 @@
+use core::iter::FromIterator;
+use core::ops::RangeBounds;
+
+use super::*;
+
+pub(crate) use positional::Positional;
+
+// Public only inside this private module, like the balancing policies, so the bulk operations
+// shared by the trees of this crate can't be used with other types.
+mod positional {
+    use super::*;
+
+    pub trait Positional {
+        type Value: Ord;
+        type Node: SizedNode<Value = Self::Value>;
+
+        fn root(&self) -> Option<&Self::Node>;
+
+        // Removes the value at `index` in sorted order.
+        fn take_at(&mut self, index: usize) -> Option<Self::Value>;
+
+        // Keeps only the values `f` returns true for, rebuilding the tree in O(n).
+        fn retain<F: FnMut(&Self::Value) -> bool>(&mut self, f: F);
+    }
+}
+
+impl<T: Ord, A: Allocator + Clone> Positional for RBTree<T, A> {
+    type Value = T;
+    type Node = Node<T, A>;
+
+    fn root(&self) -> Option<&Node<T, A>> {
+        self.root.as_deref()
+    }
+
+    fn take_at(&mut self, index: usize) -> Option<T> {
+        RBTree::take_at(self, index)
+    }
+
+    fn retain<F: FnMut(&T) -> bool>(&mut self, f: F) {
+        RBTree::retain(self, f)
+    }
+}
+
+// Deletes the values inside `range` one by one if that is quicker than rebuilding the tree
+// without them, which it is while there are few of them.
+pub fn remove_range<C: Positional, R: RangeBounds<C::Value>>(tree: &mut C, range: R) -> usize {
+    let (start, end) = range_positions(tree.root(), &range);
+    let count = end - start;
+    let len = size(tree.root());
+    // every delete costs about log2(len) steps
+    let depth = (usize::BITS - len.leading_zeros()) as usize;
+    if count.saturating_mul(depth) < len {
+        for _ in 0..count {
+            tree.take_at(start);
+        }
+    } else if count > 0 {
+        tree.retain(|value| !range.contains(value));
+    }
+    count
+}
+
+impl<T: Ord, A: Allocator + Clone> RBTree<T, A> {
+    /// Keeps only the values for which `f` returns true. The tree is rebuilt in O(n).
+    pub fn retain<F: FnMut(&T) -> bool>(&mut self, mut f: F) {
+        let mut kept = Vec::new();
+        for value in self.take_all() {
+            if f(&value) {
+                kept.push(value);
+            } else {
+                self.observers.notify(Event::Delete(&value));
+            }
+        }
+        self.root = build_from_sorted(kept, &self.alloc);
+    }
+
+    /// Removes all values from the tree, yielding them in order.
+    pub fn drain(&mut self) -> iter::IntoIter<T, A> {
+        if !self.observers.is_empty() {
+            for value in iter::Iter::from_root(self.root.as_deref()) {
+                self.observers.notify(Event::Delete(value));
+            }
+        }
+        self.take_all()
+    }
+
+    // Empties the tree without notifying the observers, for callers that put values back.
+    pub(crate) fn take_all(&mut self) -> iter::IntoIter<T, A> {
+        iter::IntoIter::from_root(self.root.take())
+    }
+
+    /// Returns an iterator that removes the values for which `pred` returns true and yields
+    /// them in order. Like `Vec::extract_if`, it is lazy: values the iterator doesn't get to
+    /// before it is dropped stay in the tree. Every value it looks at costs O(log n), and
+    /// every value it removes another O(log n).
+    pub fn extract_if<F: FnMut(&T) -> bool>(&mut self, pred: F) -> ExtractIf<'_, Self, F> {
+        ExtractIf::new(self, pred)
+    }
+
+    /// Removes every value inside `range` and returns how many were removed. The values are
+    /// counted first in O(log n). A few of them are deleted one by one, if deleting them
+    /// would take longer than rebuilding the tree, the tree is rebuilt in O(n).
+    pub fn remove_range<R: RangeBounds<T>>(&mut self, range: R) -> usize {
+        remove_range(self, range)
+    }
+}
+
+/// Iterator returned by `extract_if` on the trees of this crate.
+pub struct ExtractIf<'a, C, F> {
+    tree: &'a mut C,
+    pred: F,
+    // the index of the first value `pred` hasn't seen yet
+    index: usize,
+}
+
+impl<'a, C: Positional, F: FnMut(&C::Value) -> bool> ExtractIf<'a, C, F> {
+    pub(crate) fn new(tree: &'a mut C, pred: F) -> ExtractIf<'a, C, F> {
+        ExtractIf { tree, pred, index: 0 }
+    }
+}
+
+impl<'a, C: Positional, F: FnMut(&C::Value) -> bool> Iterator for ExtractIf<'a, C, F> {
+    type Item = C::Value;
+
+    fn next(&mut self) -> Option<C::Value> {
+        while let Some(value) = select(self.tree.root(), self.index) {
+            if (self.pred)(value) {
+                return self.tree.take_at(self.index);
+            }
+            self.index += 1;
+        }
+        None
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        (0, Some(size(self.tree.root()) - self.index))
+    }
+}
+
+/// Sorts the values and builds the tree in O(n). The sort is stable, so equal values keep
+/// the order they had, like repeated inserts would place them.
+impl<T: Ord> FromIterator<T> for RBTree<T> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        let mut values: Vec<T> = iter.into_iter().collect();
+        values.sort();
+        let mut tree = RBTree::new();
+        tree.root = build_from_sorted(values, &tree.alloc);
+        tree
+    }
+}
+
+// Builds a balanced tree from values that are already in order. Every path has
+// the same number of black nodes; only the nodes on an incomplete last level are red.
+pub fn build_from_sorted<T: Ord, A: Allocator + Clone>(values: Vec<T>, alloc: &A) -> Option<Box<Node<T, A>, A>> {
+    let len = values.len();
+    build_subtree(&mut values.into_iter(), len, 0, red_depth(len), alloc)
+}
+
+// Depth of the first level that is not completely filled in a balanced tree of `len` nodes.
+pub fn red_depth(len: usize) -> usize {
+    (usize::BITS - 1 - (len + 1).leading_zeros()) as usize
+}
+
+fn build_subtree<T: Ord, A: Allocator + Clone, I: Iterator<Item = T>>(
+    values: &mut I,
+    len: usize,
+    depth: usize,
+    red_depth: usize,
+    alloc: &A,
+) -> Option<Box<Node<T, A>, A>> {
+    if len == 0 {
+        return None;
+    }
+    let left_len = (len - 1) / 2;
+    let left = build_subtree(values, left_len, depth + 1, red_depth, alloc);
+    let value = values.next().expect("There should be as many values as the length says");
+    let right = build_subtree(values, len - 1 - left_len, depth + 1, red_depth, alloc);
+    let color = if depth == red_depth { Color::Red } else { Color::Black };
+    Some(Box::new_in(Node { color, value, left, right, size: len }, alloc.clone()))
+}
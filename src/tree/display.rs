@@ -0,0 +1,114 @@
+use alloc::string::String;
+
+use super::*;
+
+const RED: &str = "\x1b[31m";
+const RESET: &str = "\x1b[0m";
+
+struct Charset {
+    branch: &'static str,
+    last_branch: &'static str,
+    vertical: &'static str,
+    space: &'static str,
+    leaf: &'static str,
+    cut: &'static str,
+}
+
+const UNICODE: Charset = Charset { branch: "├── ", last_branch: "└── ", vertical: "│   ", space: "    ", leaf: "·", cut: "…" };
+const ASCII: Charset = Charset { branch: "|-- ", last_branch: "`-- ", vertical: "|   ", space: "    ", leaf: ".", cut: "..." };
+
+/// Pretty printer for an `RBTree`, created with `RBTree::pretty`.
+///
+/// The tree is drawn sideways with the root on the first line and the left child above the right one.
+/// Red nodes are drawn in red, or marked with `(R)` when colors are turned off.
+pub struct Pretty<'a, T: Ord, A: Allocator = Global> {
+    tree: &'a RBTree<T, A>,
+    ascii: bool,
+    colors: bool,
+    max_depth: Option<usize>,
+}
+
+impl<'a, T: Ord, A: Allocator> Pretty<'a, T, A> {
+    /// Draws with plain ASCII characters and without ANSI colors.
+    pub fn ascii(mut self) -> Self {
+        self.ascii = true;
+        self.colors = false;
+        self
+    }
+
+    pub fn colors(mut self, colors: bool) -> Self {
+        self.colors = colors;
+        self
+    }
+
+    /// Only draws nodes up to the given depth, the root being at depth 0. Deeper subtrees are cut off.
+    pub fn max_depth(mut self, max_depth: usize) -> Self {
+        self.max_depth = Some(max_depth);
+        self
+    }
+
+    fn fmt_node(&self, formatter: &mut fmt::Formatter, node: &Node<T, A>, prefix: &mut String, depth: usize) -> fmt::Result
+    where
+        T: fmt::Display,
+    {
+        match (node.color, self.colors) {
+            (Color::Red, true) => writeln!(formatter, "{}{}{}", RED, node.value, RESET)?,
+            (Color::Red, false) => writeln!(formatter, "{} (R)", node.value)?,
+            (Color::Black, _) => writeln!(formatter, "{}", node.value)?,
+        }
+        if node.left.is_none() && node.right.is_none() {
+            return Ok(());
+        }
+
+        let charset = if self.ascii { &ASCII } else { &UNICODE };
+        if self.max_depth.is_some_and(|max_depth| depth >= max_depth) {
+            return writeln!(formatter, "{}{}{}", prefix, charset.last_branch, charset.cut);
+        }
+        let children = [(node.left.as_deref(), false), (node.right.as_deref(), true)];
+        for (child, is_last) in children.iter() {
+            let (branch, continuation) = if *is_last {
+                (charset.last_branch, charset.space)
+            } else {
+                (charset.branch, charset.vertical)
+            };
+            formatter.write_str(prefix)?;
+            formatter.write_str(branch)?;
+            match child {
+                Some(child) => {
+                    prefix.push_str(continuation);
+                    self.fmt_node(formatter, child, prefix, depth + 1)?;
+                    prefix.truncate(prefix.len() - continuation.len());
+                },
+                None => writeln!(formatter, "{}", charset.leaf)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<'a, T: Ord + fmt::Display, A: Allocator> fmt::Display for Pretty<'a, T, A> {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        match &self.tree.root {
+            Some(root_node) => self.fmt_node(formatter, root_node, &mut String::new(), 0),
+            None => formatter.write_str("Empty tree\n"),
+        }
+    }
+}
+
+impl<T: Ord, A: Allocator> RBTree<T, A> {
+    /// Returns a configurable pretty printer. By default it uses box-drawing characters and ANSI colors.
+    pub fn pretty(&self) -> Pretty<'_, T, A> {
+        Pretty { tree: self, ascii: false, colors: true, max_depth: None }
+    }
+}
+
+/// Draws the tree with `RBTree::pretty`. The alternate flag (`{:#}`) switches to plain ASCII.
+impl<T: Ord + fmt::Display, A: Allocator> fmt::Display for RBTree<T, A> {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        if formatter.alternate() {
+            self.pretty().ascii().fmt(formatter)
+        } else {
+            self.pretty().fmt(formatter)
+        }
+    }
+}
@@ -0,0 +1,119 @@
+use alloc::format;
+use alloc::string::String;
+use core::cmp::Ordering;
+#[cfg(feature = "std")]
+use std::io;
+
+use super::*;
+
+const HIGHLIGHT: &str = "color=gold, penwidth=3";
+
+impl<T: Ord + fmt::Debug, A: Allocator> RBTree<T, A> {
+    /// Renders the tree in Graphviz DOT format. Leaves are drawn as explicit NIL nodes.
+    pub fn to_dot(&self) -> String {
+        let mut dot = String::new();
+        self.fmt_dot(&mut dot, None).expect("Writing to a String can not fail");
+        dot
+    }
+
+    /// Like `to_dot`, but also highlights the nodes and edges visited when searching for `value`.
+    pub fn to_dot_with_path(&self, value: &T) -> String {
+        let mut dot = String::new();
+        self.fmt_dot(&mut dot, Some(value)).expect("Writing to a String can not fail");
+        dot
+    }
+
+    #[cfg(feature = "std")]
+    pub fn write_dot<W: io::Write>(&self, out: &mut W) -> io::Result<()> {
+        write_through(out, |writer| self.fmt_dot(writer, None))
+    }
+
+    #[cfg(feature = "std")]
+    pub fn write_dot_with_path<W: io::Write>(&self, out: &mut W, value: &T) -> io::Result<()> {
+        write_through(out, |writer| self.fmt_dot(writer, Some(value)))
+    }
+
+    fn fmt_dot<W: fmt::Write>(&self, out: &mut W, search: Option<&T>) -> fmt::Result {
+        out.write_str("digraph RBTree {\n")?;
+        out.write_str("    node [style=filled, fontcolor=white, fontname=\"Helvetica\"];\n")?;
+        let mut next_id = 0;
+        write_dot_subtree(out, self.root.as_deref(), search, &mut next_id)?;
+        out.write_str("}\n")
+    }
+}
+
+// Writes the node or leaf and everything below it, returns the id of the node.
+// `search` is the value whose search path goes through this node, if any.
+fn write_dot_subtree<T: Ord + fmt::Debug, A: Allocator, W: fmt::Write>(out: &mut W, node_or_leaf: Option<&Node<T, A>>, search: Option<&T>, next_id: &mut usize) -> Result<usize, fmt::Error> {
+    let id = *next_id;
+    *next_id += 1;
+    let node = match node_or_leaf {
+        Some(node) => node,
+        None => {
+            write!(out, "    n{} [label=\"NIL\", shape=box, fillcolor=black, fontsize=8, width=0.3, height=0.2", id)?;
+            if search.is_some() {
+                write!(out, ", {}", HIGHLIGHT)?;
+            }
+            out.write_str("];\n")?;
+            return Ok(id);
+        }
+    };
+
+    let fill = match node.color {
+        Color::Red => "red",
+        Color::Black => "black",
+    };
+    write!(out, "    n{} [label=\"{}\", fillcolor={}", id, escape(&format!("{:?}", node.value)), fill)?;
+    let next_on_path = search.map(|value| value.cmp(&node.value));
+    if search.is_some() {
+        write!(out, ", {}", HIGHLIGHT)?;
+    }
+    out.write_str("];\n")?;
+
+    let left_search = if next_on_path == Some(Ordering::Less) { search } else { None };
+    let right_search = if next_on_path == Some(Ordering::Greater) { search } else { None };
+    let left_id = write_dot_subtree(out, node.left.as_deref(), left_search, next_id)?;
+    let right_id = write_dot_subtree(out, node.right.as_deref(), right_search, next_id)?;
+    write_dot_edge(out, id, left_id, left_search.is_some())?;
+    write_dot_edge(out, id, right_id, right_search.is_some())?;
+    Ok(id)
+}
+
+fn write_dot_edge<W: fmt::Write>(out: &mut W, from: usize, to: usize, highlighted: bool) -> fmt::Result {
+    if highlighted {
+        writeln!(out, "    n{} -> n{} [{}];", from, to, HIGHLIGHT)
+    } else {
+        writeln!(out, "    n{} -> n{};", from, to)
+    }
+}
+
+fn escape(label: &str) -> String {
+    label.replace('\\', "\\\\").replace('"', "\\\"")
+}
+
+// Runs a fmt::Write based writer against an io::Write, keeping the io error if one happens.
+#[cfg(feature = "std")]
+fn write_through<W: io::Write, F: FnOnce(&mut IoWriter<W>) -> fmt::Result>(out: &mut W, write: F) -> io::Result<()> {
+    let mut writer = IoWriter { out, error: None };
+    let result = write(&mut writer);
+    match writer.error {
+        Some(error) => Err(error),
+        None => result.map_err(|_| io::Error::other("formatting the tree failed")),
+    }
+}
+
+#[cfg(feature = "std")]
+struct IoWriter<'a, W: io::Write> {
+    out: &'a mut W,
+    error: Option<io::Error>,
+}
+
+#[cfg(feature = "std")]
+impl<'a, W: io::Write> fmt::Write for IoWriter<'a, W> {
+    fn write_str(&mut self, s: &str) -> fmt::Result {
+        self.out.write_all(s.as_bytes()).map_err(|error| {
+            self.error = Some(error);
+            fmt::Error
+        })
+    }
+}
@@ -0,0 +1,114 @@
+use super::*;
+
+/// A read-only view of a node in an `RBTree`.
+pub struct NodeRef<'a, T: Ord, A: Allocator = Global> {
+    node: &'a Node<T, A>,
+}
+
+impl<'a, T: Ord, A: Allocator> Clone for NodeRef<'a, T, A> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<'a, T: Ord, A: Allocator> Copy for NodeRef<'a, T, A> {}
+
+impl<'a, T: Ord, A: Allocator> NodeRef<'a, T, A> {
+    pub fn value(&self) -> &'a T {
+        &self.node.value
+    }
+
+    pub fn color(&self) -> Color {
+        self.node.color
+    }
+
+    pub fn left(&self) -> Option<NodeRef<'a, T, A>> {
+        self.child(Direction::Left)
+    }
+
+    pub fn right(&self) -> Option<NodeRef<'a, T, A>> {
+        self.child(Direction::Right)
+    }
+
+    pub fn child(&self, dir: Direction) -> Option<NodeRef<'a, T, A>> {
+        let child = match dir {
+            Direction::Left => self.node.left.as_deref(),
+            Direction::Right => self.node.right.as_deref(),
+        };
+        child.map(|node| NodeRef { node })
+    }
+}
+
+/// Shape statistics of an `RBTree`, see `RBTree::stats`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct TreeStats {
+    /// `depth_histogram[d]` is the number of nodes at depth `d`, the root being at depth 0.
+    pub depth_histogram: Vec<usize>,
+    /// Average number of nodes visited when searching for a value that is in the tree.
+    pub average_search_depth: f64,
+}
+
+impl<T: Ord, A: Allocator> RBTree<T, A> {
+    pub fn root(&self) -> Option<NodeRef<'_, T, A>> {
+        self.root.as_deref().map(|node| NodeRef { node })
+    }
+
+    /// Number of nodes on the longest path from the root to a leaf.
+    pub fn height(&self) -> usize {
+        subtree_height(self.root.as_deref())
+    }
+
+    /// Number of black nodes on a path from the root to a leaf. Assumes the tree is valid.
+    pub fn black_height(&self) -> usize {
+        let mut height = 0;
+        let mut next = self.root.as_deref();
+        while let Some(node) = next {
+            if node.is_black() {
+                height += 1;
+            }
+            next = node.left.as_deref();
+        }
+        height
+    }
+
+    pub fn red_count(&self) -> usize {
+        count_red(self.root.as_deref())
+    }
+
+    pub fn stats(&self) -> TreeStats {
+        let mut depth_histogram = Vec::new();
+        fill_histogram(self.root.as_deref(), 0, &mut depth_histogram);
+        let nodes: usize = depth_histogram.iter().sum();
+        let visited: usize = depth_histogram.iter().enumerate().map(|(depth, count)| (depth + 1) * count).sum();
+        let average_search_depth = if nodes == 0 { 0.0 } else { visited as f64 / nodes as f64 };
+        TreeStats { depth_histogram, average_search_depth }
+    }
+}
+
+fn subtree_height<T: Ord, A: Allocator>(node_or_leaf: Option<&Node<T, A>>) -> usize {
+    match node_or_leaf {
+        Some(node) => 1 + subtree_height(node.left.as_deref()).max(subtree_height(node.right.as_deref())),
+        None => 0,
+    }
+}
+
+fn count_red<T: Ord, A: Allocator>(node_or_leaf: Option<&Node<T, A>>) -> usize {
+    match node_or_leaf {
+        Some(node) => {
+            let own = if node.is_black() { 0 } else { 1 };
+            own + count_red(node.left.as_deref()) + count_red(node.right.as_deref())
+        },
+        None => 0,
+    }
+}
+
+fn fill_histogram<T: Ord, A: Allocator>(node_or_leaf: Option<&Node<T, A>>, depth: usize, histogram: &mut Vec<usize>) {
+    if let Some(node) = node_or_leaf {
+        if histogram.len() == depth {
+            histogram.push(0);
+        }
+        histogram[depth] += 1;
+        fill_histogram(node.left.as_deref(), depth + 1, histogram);
+        fill_histogram(node.right.as_deref(), depth + 1, histogram);
+    }
+}
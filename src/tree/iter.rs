@@ -1,16 +1,19 @@
 
+use alloc::collections::VecDeque;
+use core::ops::{Bound, RangeBounds};
+
 use super::*;
 
-enum IterTask<T: Ord> {
+enum IterTask<T: Ord, A: Allocator> {
     Value(T),
-    Node(Box<Node<T>>),
+    Node(Box<Node<T, A>, A>),
 }
 
-pub struct IntoIter<T: Ord> {
-    tasks: Vec<IterTask<T>>,
+pub struct IntoIter<T: Ord, A: Allocator = Global> {
+    tasks: Vec<IterTask<T, A>>,
 }
 
-fn add_tasks<T: Ord>(tasks: &mut Vec<IterTask<T>>, node: Box<Node<T>>) {
+fn add_tasks<T: Ord, A: Allocator>(tasks: &mut Vec<IterTask<T, A>>, node: Node<T, A>) {
     if let Some(right_node) = node.right {
         tasks.push(IterTask::Node(right_node));
     }
@@ -20,17 +23,21 @@ fn add_tasks<T: Ord>(tasks: &mut Vec<IterTask<T>>, node: Box<Node<T>>) {
     }
 }
 
-impl<T: Ord> IntoIter<T> {
-    pub fn new(tree: RBTree<T>) -> IntoIter<T> {
+impl<T: Ord, A: Allocator> IntoIter<T, A> {
+    pub fn new(tree: RBTree<T, A>) -> IntoIter<T, A> {
+        IntoIter::from_root(tree.root)
+    }
+
+    pub(crate) fn from_root(root: Option<Box<Node<T, A>, A>>) -> IntoIter<T, A> {
         let mut tasks = Vec::new();
-        if let Some(root_node) = tree.root {
-            add_tasks::<T>(&mut tasks, root_node);
+        if let Some(root_node) = root {
+            add_tasks(&mut tasks, Box::into_inner(root_node));
         }
-        IntoIter { tasks: tasks }
+        IntoIter { tasks }
     }
 }
 
-impl<T: Ord> Iterator for IntoIter<T> {
+impl<T: Ord, A: Allocator> Iterator for IntoIter<T, A> {
     type Item = T;
 
     fn next(&mut self) -> Option<Self::Item> {
@@ -38,10 +45,160 @@ impl<T: Ord> Iterator for IntoIter<T> {
             match task {
                 IterTask::Value(v) => return Some(v),
                 IterTask::Node(node) => {
-                    add_tasks(&mut self.tasks, node);
+                    add_tasks(&mut self.tasks, Box::into_inner(node));
                 }
             }
         }
         None
     }
 }
+
+pub struct Iter<'a, T: Ord, A: Allocator = Global> {
+    stack: Vec<&'a Node<T, A>>,
+}
+
+impl<'a, T: Ord, A: Allocator> Iter<'a, T, A> {
+    pub fn new(tree: &'a RBTree<T, A>) -> Iter<'a, T, A> {
+        Iter::from_root(tree.root.as_deref())
+    }
+
+    pub(crate) fn from_root(root: Option<&'a Node<T, A>>) -> Iter<'a, T, A> {
+        let mut iter = Iter { stack: Vec::new() };
+        iter.push_left_spine(root);
+        iter
+    }
+
+    fn push_left_spine(&mut self, mut next: Option<&'a Node<T, A>>) {
+        while let Some(node) = next {
+            self.stack.push(node);
+            next = node.left.as_deref();
+        }
+    }
+}
+
+impl<'a, T: Ord, A: Allocator> Iterator for Iter<'a, T, A> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let node = self.stack.pop()?;
+        self.push_left_spine(node.right.as_deref());
+        Some(&node.value)
+    }
+}
+
+// A value still to be yielded or a subtree still to be split up, see `IterMut`.
+enum Piece<'a, T: Ord, A: Allocator> {
+    Value(&'a mut T),
+    Subtree(&'a mut Node<T, A>),
+}
+
+// In-order iteration over mutable values, from both ends. The pieces left to visit are
+// disjoint borrows kept in order, so either end can be split up without touching the other.
+// Only the map uses it: it hands out the parts of its values the ordering doesn't look at.
+pub(crate) struct IterMut<'a, T: Ord, A: Allocator = Global> {
+    pieces: VecDeque<Piece<'a, T, A>>,
+}
+
+impl<'a, T: Ord, A: Allocator> IterMut<'a, T, A> {
+    pub(crate) fn from_root(root: Option<&'a mut Node<T, A>>) -> IterMut<'a, T, A> {
+        IterMut { pieces: root.map(Piece::Subtree).into_iter().collect() }
+    }
+
+    // The values inside `range`, which can bound any borrowed form of them. Only the nodes on
+    // the paths to the two ends are split up front, everything between stays a whole subtree.
+    pub(crate) fn from_range<Q: ?Sized + Ord, R: RangeBounds<Q>>(root: Option<&'a mut Node<T, A>>, range: &R) -> IterMut<'a, T, A>
+    where
+        T: Borrow<Q>,
+    {
+        let mut pieces = VecDeque::new();
+        add_range(root, range, false, false, &mut pieces);
+        IterMut { pieces }
+    }
+}
+
+// `lower_done` and `upper_done` tell whether the subtree is already known to be above the
+// start or below the end of the range.
+fn add_range<'a, T, A, Q, R>(node_or_leaf: Option<&'a mut Node<T, A>>, range: &R, lower_done: bool, upper_done: bool, pieces: &mut VecDeque<Piece<'a, T, A>>)
+where
+    T: Ord + Borrow<Q>,
+    A: Allocator,
+    Q: ?Sized + Ord,
+    R: RangeBounds<Q>,
+{
+    let node = match node_or_leaf {
+        Some(node) => node,
+        None => return,
+    };
+    if lower_done && upper_done {
+        pieces.push_back(Piece::Subtree(node));
+        return;
+    }
+    let Node { left, value, right, .. } = node;
+    let key: &Q = (*value).borrow();
+    let above_lower = lower_done
+        || match range.start_bound() {
+            Bound::Included(start) => key >= start,
+            Bound::Excluded(start) => key > start,
+            Bound::Unbounded => true,
+        };
+    let below_upper = upper_done
+        || match range.end_bound() {
+            Bound::Included(end) => key <= end,
+            Bound::Excluded(end) => key < end,
+            Bound::Unbounded => true,
+        };
+    // values on the left are at most this one, values on the right at least
+    if above_lower {
+        add_range(left.as_deref_mut(), range, lower_done, below_upper, pieces);
+    }
+    if above_lower && below_upper {
+        pieces.push_back(Piece::Value(value));
+    }
+    if below_upper {
+        add_range(right.as_deref_mut(), range, above_lower, upper_done, pieces);
+    }
+}
+
+impl<'a, T: Ord, A: Allocator> Iterator for IterMut<'a, T, A> {
+    type Item = &'a mut T;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        while let Some(piece) = self.pieces.pop_front() {
+            match piece {
+                Piece::Value(value) => return Some(value),
+                Piece::Subtree(node) => {
+                    let Node { left, value, right, .. } = node;
+                    if let Some(right_node) = right.as_deref_mut() {
+                        self.pieces.push_front(Piece::Subtree(right_node));
+                    }
+                    self.pieces.push_front(Piece::Value(value));
+                    if let Some(left_node) = left.as_deref_mut() {
+                        self.pieces.push_front(Piece::Subtree(left_node));
+                    }
+                },
+            }
+        }
+        None
+    }
+}
+
+impl<'a, T: Ord, A: Allocator> DoubleEndedIterator for IterMut<'a, T, A> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        while let Some(piece) = self.pieces.pop_back() {
+            match piece {
+                Piece::Value(value) => return Some(value),
+                Piece::Subtree(node) => {
+                    let Node { left, value, right, .. } = node;
+                    if let Some(left_node) = left.as_deref_mut() {
+                        self.pieces.push_back(Piece::Subtree(left_node));
+                    }
+                    self.pieces.push_back(Piece::Value(value));
+                    if let Some(right_node) = right.as_deref_mut() {
+                        self.pieces.push_back(Piece::Subtree(right_node));
+                    }
+                },
+            }
+        }
+        None
+    }
+}
@@ -0,0 +1,518 @@
+use core::cmp::Ordering;
+use core::iter::FromIterator;
+use core::ops::RangeBounds;
+
+use super::*;
+
+type Link<T> = Option<Box<Node<T>>>;
+
+/// A left-leaning red-black tree: red links only ever lean left, so every 3-node of the
+/// equivalent 2-3 tree has a single shape. The code is much shorter than `RBTree`'s and
+/// maps directly onto Sedgewick, "Left-leaning Red-Black Trees" (2008), at the cost of
+/// more rotations per operation.
+///
+/// Like `RBTree` it is a multiset.
+pub struct LLRBTree<T: Ord> {
+    root: Link<T>,
+}
+
+fn is_red<T: Ord>(link: &Link<T>) -> bool {
+    get_color(link.as_deref()) == Color::Red
+}
+
+fn flip(color: Color) -> Color {
+    match color {
+        Color::Red => Color::Black,
+        Color::Black => Color::Red,
+    }
+}
+
+// Moves `node` down to the `dir` side of its other child, which takes over its color.
+fn rotate<T: Ord>(mut node: Box<Node<T>>, dir: Direction) -> Box<Node<T>> {
+    let color = node.color;
+    node.color = Color::Red;
+    let mut top = Node::rotate(node, RotationType::Single(dir));
+    top.color = color;
+    top
+}
+
+fn flip_colors<T: Ord>(node: &mut Node<T>) {
+    node.color = flip(node.color);
+    if let Some(ref mut left) = node.left {
+        left.color = flip(left.color);
+    }
+    if let Some(ref mut right) = node.right {
+        right.color = flip(right.color);
+    }
+}
+
+// Restores the left-leaning shape on the way back up, after a child was replaced.
+fn balance<T: Ord>(mut node: Box<Node<T>>) -> Box<Node<T>> {
+    node.update_size();
+    if is_red(&node.right) && !is_red(&node.left) {
+        node = rotate(node, Direction::Left);
+    }
+    if is_red(&node.left) && is_red(&node.left.as_ref().unwrap().left) {
+        node = rotate(node, Direction::Right);
+    }
+    if is_red(&node.left) && is_red(&node.right) {
+        flip_colors(&mut node);
+    }
+    node
+}
+
+fn insert<T: Ord>(link: Link<T>, value: T) -> Box<Node<T>> {
+    let mut node = match link {
+        Some(node) => node,
+        None => return Box::new(Node::new(Color::Red, value)),
+    };
+    if value < node.value {
+        node.left = Some(insert(node.left.take(), value));
+    } else {
+        node.right = Some(insert(node.right.take(), value));
+    }
+    balance(node)
+}
+
+// Makes the left child or one of its children red, so the delete can go left.
+fn move_red_left<T: Ord>(mut node: Box<Node<T>>) -> Box<Node<T>> {
+    flip_colors(&mut node);
+    if is_red(&node.right.as_ref().unwrap().left) {
+        let right = node.right.take().unwrap();
+        node.right = Some(rotate(right, Direction::Right));
+        node = rotate(node, Direction::Left);
+        flip_colors(&mut node);
+    }
+    node
+}
+
+// Makes the right child or one of its children red, so the delete can go right. Returns
+// whether the left child was rotated up.
+fn move_red_right<T: Ord>(mut node: Box<Node<T>>) -> (Box<Node<T>>, bool) {
+    flip_colors(&mut node);
+    if is_red(&node.left.as_ref().unwrap().left) {
+        node = rotate(node, Direction::Right);
+        flip_colors(&mut node);
+        return (node, true);
+    }
+    (node, false)
+}
+
+// Removes the smallest value of the subtree, returning what is left of it and the value.
+fn delete_min<T: Ord>(mut node: Box<Node<T>>) -> (Link<T>, T) {
+    if node.left.is_none() {
+        return (None, Box::into_inner(node).value);
+    }
+    if !is_red(&node.left) && !is_red(&node.left.as_ref().unwrap().left) {
+        node = move_red_left(node);
+    }
+    let (left, min) = delete_min(node.left.take().unwrap());
+    node.left = left;
+    (Some(balance(node)), min)
+}
+
+// What a delete looks for: a value, or a position in sorted order relative to the subtree. The
+// delete rotates on its way down, so a key is compared with whatever node is on top of the
+// subtree at the time, and only changes when the delete goes right.
+trait Key<T: Ord> {
+    fn locate(&self, node: &Node<T>) -> Ordering;
+
+    fn go_right(&mut self, _node: &Node<T>) {}
+}
+
+impl<T: Ord> Key<T> for &T {
+    fn locate(&self, node: &Node<T>) -> Ordering {
+        (*self).cmp(&node.value)
+    }
+}
+
+impl<T: Ord> Key<T> for Position {
+    fn locate(&self, node: &Node<T>) -> Ordering {
+        self.0.cmp(&subtree_size(node.left.as_deref()))
+    }
+
+    fn go_right(&mut self, node: &Node<T>) {
+        self.0 -= subtree_size(node.left.as_deref()) + 1;
+    }
+}
+
+// What `key` looks for must be in the subtree.
+fn delete<T: Ord>(mut node: Box<Node<T>>, key: &mut impl Key<T>) -> (Link<T>, T) {
+    let removed;
+    if key.locate(&node) == Ordering::Less {
+        if !is_red(&node.left) && !is_red(&node.left.as_ref().unwrap().left) {
+            node = move_red_left(node);
+        }
+        let (left, value) = delete(node.left.take().unwrap(), key);
+        node.left = left;
+        removed = value;
+    } else {
+        if is_red(&node.left) {
+            node = rotate(node, Direction::Right);
+        }
+        if key.locate(&node) == Ordering::Equal && node.right.is_none() {
+            return (None, Box::into_inner(node).value);
+        }
+        let mut found = key.locate(&node) == Ordering::Equal;
+        if !is_red(&node.right) && !is_red(&node.right.as_ref().unwrap().left) {
+            let (moved, rotated) = move_red_right(node);
+            node = moved;
+            // the node that was checked moved to the right, even if the new one is equal too
+            found &= !rotated;
+        }
+        if found {
+            let (right, min) = delete_min(node.right.take().unwrap());
+            node.right = right;
+            removed = core::mem::replace(&mut node.value, min);
+        } else {
+            key.go_right(&node);
+            let (right, value) = delete(node.right.take().unwrap(), key);
+            node.right = right;
+            removed = value;
+        }
+    }
+    (Some(balance(node)), removed)
+}
+
+// Builds a subtree of `len` values taken from `values`, as a 2-3 tree with its values spread
+// evenly whose 3-nodes are black nodes with a red left child. Every path has the same number
+// h of black nodes, for which the subtree holds between 2^h - 1 and `capacity` = 3^h - 1 values.
+fn build<T: Ord, I: Iterator<Item = T>>(values: &mut I, len: usize, capacity: usize) -> Link<T> {
+    fn next<T, I: Iterator<Item = T>>(values: &mut I) -> T {
+        values.next().expect("There should be as many values as the length says")
+    }
+
+    if len == 0 {
+        return None;
+    }
+    let child_capacity = (capacity - 2) / 3;
+    let mut node = if len - 1 <= 2 * child_capacity {
+        let left_len = (len - 1) / 2;
+        let left = build(values, left_len, child_capacity);
+        let mut node = Node::new(Color::Black, next(values));
+        node.left = left;
+        node.right = build(values, len - 1 - left_len, child_capacity);
+        node
+    } else {
+        let left_len = (len - 2) / 3;
+        let middle_len = (len - 2 - left_len) / 2;
+        let left = build(values, left_len, child_capacity);
+        let mut red = Node::new(Color::Red, next(values));
+        red.left = left;
+        red.right = build(values, middle_len, child_capacity);
+        red.update_size();
+        let mut node = Node::new(Color::Black, next(values));
+        node.left = Some(Box::new(red));
+        node.right = build(values, len - 2 - left_len - middle_len, child_capacity);
+        node
+    };
+    node.update_size();
+    Some(Box::new(node))
+}
+
+fn build_from_sorted<T: Ord>(values: Vec<T>) -> Link<T> {
+    // the fewest black nodes per path that can hold all the values
+    let len = values.len();
+    let mut capacity: usize = 0;
+    while capacity < len {
+        capacity = capacity.saturating_mul(3).saturating_add(2);
+    }
+    build(&mut values.into_iter(), len, capacity)
+}
+
+impl<T: Ord> LLRBTree<T> {
+    pub fn new() -> LLRBTree<T> {
+        LLRBTree { root: None }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.root.is_none()
+    }
+
+    /// Number of values in the tree, duplicates included.
+    pub fn len(&self) -> usize {
+        subtree_size(self.root.as_deref())
+    }
+
+    pub fn iter(&self) -> Iter<'_, T> {
+        Iter::from_root(self.root.as_deref())
+    }
+
+    pub fn contains(&self, value: T) -> bool {
+        self.get(&value).is_some()
+    }
+
+    /// A value equal to `value`, which can be any borrowed form of the values, as long as it
+    /// orders the same way.
+    pub fn get<Q: ?Sized + Ord>(&self, value: &Q) -> Option<&T>
+    where
+        T: Borrow<Q>,
+    {
+        search(self.root.as_deref(), |node_value| value.cmp(node_value.borrow()))
+    }
+
+    /// The value at `index` in sorted order, counting from 0, in O(log n).
+    pub fn select(&self, index: usize) -> Option<&T> {
+        select(self.root.as_deref(), index)
+    }
+
+    /// Number of values less than `value`, which is the index of its first copy in sorted
+    /// order if it is in the tree. O(log n).
+    pub fn rank(&self, value: &T) -> usize {
+        count_while(self.root.as_deref(), |node_value| node_value < value)
+    }
+
+    /// The value closest to `query` by `distance`, in O(log n), like `RBTree::nearest`.
+    pub fn nearest<D: PartialOrd, F: Fn(&T, &T) -> D>(&self, query: &T, distance: F) -> Option<&T> {
+        nearest(self.root.as_deref(), query, distance)
+    }
+
+    /// The `k` values closest to `query` by `distance`, nearest first, in O(log n + k), like
+    /// `RBTree::k_nearest`.
+    pub fn k_nearest<D: PartialOrd, F: Fn(&T, &T) -> D>(&self, query: &T, k: usize, distance: F) -> Vec<&T> {
+        k_nearest(self.root.as_deref(), query, k, distance)
+    }
+
+    pub fn insert(&mut self, value: T) {
+        let mut root = insert(self.root.take(), value);
+        root.color = Color::Black;
+        self.root = Some(root);
+    }
+
+    pub fn delete(&mut self, value: T) -> bool {
+        self.take(&value).is_some()
+    }
+
+    /// Removes a value equal to `value` and returns the one that was stored in the tree.
+    pub fn take(&mut self, mut value: &T) -> Option<T> {
+        // the delete relies on the value being there, so it is looked up first
+        self.get(value)?;
+        Some(self.take_key(&mut value))
+    }
+
+    fn take_key(&mut self, key: &mut impl Key<T>) -> T {
+        let mut root = self.root.take().unwrap();
+        if !is_red(&root.left) && !is_red(&root.right) {
+            root.color = Color::Red;
+        }
+        let (mut root, removed) = delete(root, key);
+        if let Some(ref mut root) = root {
+            root.color = Color::Black;
+        }
+        self.root = root;
+        removed
+    }
+
+    /// Keeps only the values for which `f` returns true. The tree is rebuilt in O(n).
+    pub fn retain<F: FnMut(&T) -> bool>(&mut self, f: F) {
+        let kept: Vec<T> = self.drain().filter(f).collect();
+        self.root = build_from_sorted(kept);
+    }
+
+    /// Removes all values from the tree, yielding them in order.
+    pub fn drain(&mut self) -> IntoIter<T> {
+        IntoIter::from_root(self.root.take())
+    }
+
+    /// Returns an iterator that removes the values for which `pred` returns true, lazily like
+    /// `RBTree::extract_if`.
+    pub fn extract_if<F: FnMut(&T) -> bool>(&mut self, pred: F) -> ExtractIf<'_, Self, F> {
+        ExtractIf::new(self, pred)
+    }
+
+    /// Removes every value inside `range` and returns how many were removed, like
+    /// `RBTree::remove_range`.
+    pub fn remove_range<R: RangeBounds<T>>(&mut self, range: R) -> usize {
+        remove_range(self, range)
+    }
+
+    /// Checks the red-black and ordering invariants, the subtree sizes and that no red node is
+    /// a right child.
+    pub fn validate(&self) -> Result<(), InvariantViolation<'_, T>> {
+        validate_colored(self.root.as_deref())?;
+        validate_sizes(self.root.as_deref(), &mut Vec::new())?;
+        match self.root.as_deref() {
+            Some(root) => find_right_red(root, &mut Vec::new()),
+            None => Ok(()),
+        }
+    }
+}
+
+impl<T: Ord> Positional for LLRBTree<T> {
+    type Value = T;
+    type Node = Node<T>;
+
+    fn root(&self) -> Option<&Node<T>> {
+        self.root.as_deref()
+    }
+
+    fn take_at(&mut self, index: usize) -> Option<T> {
+        if index >= self.len() {
+            return None;
+        }
+        Some(self.take_key(&mut Position(index)))
+    }
+
+    fn retain<F: FnMut(&T) -> bool>(&mut self, f: F) {
+        LLRBTree::retain(self, f)
+    }
+}
+
+fn find_right_red<'a, T: Ord>(node: &'a Node<T>, path: &mut Vec<Direction>) -> Result<(), InvariantViolation<'a, T>> {
+    if let Some(left) = node.left.as_deref() {
+        path.push(Direction::Left);
+        find_right_red(left, path)?;
+        path.pop();
+    }
+    if let Some(right) = node.right.as_deref() {
+        path.push(Direction::Right);
+        if right.color == Color::Red {
+            return Err(InvariantViolation::RightRed { value: &right.value, path: path.clone() });
+        }
+        find_right_red(right, path)?;
+        path.pop();
+    }
+    Ok(())
+}
+
+impl<T: Ord> Default for LLRBTree<T> {
+    fn default() -> LLRBTree<T> {
+        LLRBTree::new()
+    }
+}
+
+impl<T: Ord> IntoIterator for LLRBTree<T> {
+    type Item = T;
+    type IntoIter = IntoIter<T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        IntoIter::from_root(self.root)
+    }
+}
+
+impl<'a, T: Ord> IntoIterator for &'a LLRBTree<T> {
+    type Item = &'a T;
+    type IntoIter = Iter<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<T: Ord> FromIterator<T> for LLRBTree<T> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        let mut tree = LLRBTree::new();
+        for value in iter {
+            tree.insert(value);
+        }
+        tree
+    }
+}
+
+impl<T: Ord + fmt::Debug> fmt::Debug for LLRBTree<T> {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        match &self.root {
+            Some(root_node) => fmt_subtree(root_node, formatter, 0),
+            None => formatter.write_str("Empty tree\n"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_insert_and_delete() {
+        let mut tree = LLRBTree::new();
+        let values: Vec<i32> = (0..300).map(|i| (i * 37) % 101).collect();
+        for value in values.iter() {
+            tree.insert(*value);
+            assert_eq!(tree.validate(), Ok(()));
+        }
+        let mut sorted = values.clone();
+        sorted.sort();
+        assert_eq!(tree.iter().copied().collect::<Vec<i32>>(), sorted);
+
+        assert!(!tree.delete(500));
+        for value in values.iter().rev() {
+            assert!(tree.contains(*value));
+            assert!(tree.delete(*value));
+            assert_eq!(tree.validate(), Ok(()));
+        }
+        assert!(tree.is_empty());
+    }
+
+    #[test]
+    fn test_delete_duplicates() {
+        let mut tree: LLRBTree<i32> = vec![17, 17, 17, 0, 0, 0].into_iter().collect();
+
+        assert!(tree.delete(17));
+        assert_eq!(tree.validate(), Ok(()));
+        assert_eq!(tree.iter().copied().collect::<Vec<i32>>(), vec![0, 0, 0, 17, 17]);
+    }
+
+    #[test]
+    fn test_take() {
+        let mut tree: LLRBTree<(i32, &str)> = vec![(2, "b"), (1, "a"), (3, "c")].into_iter().collect();
+
+        assert_eq!(tree.take(&(2, "b")), Some((2, "b")));
+        assert_eq!(tree.take(&(2, "b")), None);
+        assert_eq!(tree.into_iter().collect::<Vec<_>>(), vec![(1, "a"), (3, "c")]);
+    }
+
+    #[test]
+    fn test_order_statistics_and_nearest() {
+        let tree: LLRBTree<i32> = (0..100).map(|i| i / 2).collect();
+        let distance = |a: &i32, b: &i32| (a - b).abs();
+
+        assert_eq!(tree.len(), 100);
+        assert_eq!(tree.get(&10), Some(&10));
+        assert_eq!(tree.get(&50), None);
+        assert_eq!(tree.select(21), Some(&10));
+        assert_eq!(tree.select(100), None);
+        assert_eq!(tree.rank(&10), 20);
+        assert_eq!(tree.nearest(&60, distance), Some(&49));
+        assert_eq!(tree.k_nearest(&49, 3, distance), vec![&49, &49, &48]);
+    }
+
+    #[test]
+    fn test_bulk() {
+        let mut tree: LLRBTree<i32> = (0..100).collect();
+
+        let mut threes = tree.extract_if(|value| value % 3 == 0);
+        assert_eq!(threes.next(), Some(0));
+        assert_eq!(threes.next(), Some(3));
+        assert_eq!(tree.len(), 98);
+        assert_eq!(tree.validate(), Ok(()));
+
+        assert_eq!(tree.remove_range(10..20), 10);
+        assert_eq!(tree.remove_range(50..), 50);
+        assert_eq!(tree.validate(), Ok(()));
+
+        tree.retain(|value| value % 2 == 0);
+        assert_eq!(tree.validate(), Ok(()));
+        assert_eq!(tree.drain().collect::<Vec<i32>>(), vec![2, 4, 6, 8, 20, 22, 24, 26, 28, 30, 32, 34, 36, 38, 40, 42, 44, 46, 48]);
+        assert!(tree.is_empty());
+    }
+
+    #[test]
+    fn test_retain_builds_valid_trees() {
+        for len in 0..200 {
+            let mut tree: LLRBTree<i32> = (0..len).collect();
+            tree.retain(|_| true);
+            assert_eq!(tree.validate(), Ok(()));
+            assert_eq!(tree.iter().copied().collect::<Vec<i32>>(), (0..len).collect::<Vec<i32>>());
+        }
+    }
+
+    #[test]
+    fn test_validate_right_red() {
+        let mut root = Node::new(Color::Black, 1);
+        root.right = Some(Box::new(Node::new(Color::Red, 2)));
+        root.size = 2;
+        let tree = LLRBTree { root: Some(Box::new(root)) };
+
+        assert_eq!(tree.validate(), Err(InvariantViolation::RightRed { value: &2, path: vec![Direction::Right] }));
+    }
+}
@@ -0,0 +1,345 @@
+//! A key-value map on top of `RBTree`.
+
+use core::iter::FromIterator;
+use core::ops::RangeBounds;
+
+use super::*;
+
+// A key with its value, ordered by the key alone.
+pub(super) struct Entry<K, V> {
+    pub(super) key: K,
+    pub(super) value: V,
+}
+
+impl<K: Ord, V> PartialEq for Entry<K, V> {
+    fn eq(&self, other: &Self) -> bool {
+        self.key == other.key
+    }
+}
+
+impl<K: Ord, V> Eq for Entry<K, V> {}
+
+impl<K: Ord, V> PartialOrd for Entry<K, V> {
+    fn partial_cmp(&self, other: &Self) -> Option<core::cmp::Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<K: Ord, V> Ord for Entry<K, V> {
+    fn cmp(&self, other: &Self) -> core::cmp::Ordering {
+        self.key.cmp(&other.key)
+    }
+}
+
+// Lets the tree find the ends of a range of keys. Lookups by any borrowed form of the key go
+// through a comparison instead, since `Borrow<Q>` for every `Q` the key borrows as would
+// overlap with `Borrow<Entry>`.
+impl<K, V> Borrow<K> for Entry<K, V> {
+    fn borrow(&self) -> &K {
+        &self.key
+    }
+}
+
+/// A map from keys to values, kept in a red-black tree ordered by the keys. Every key is in
+/// the map at most once. The values are not part of the ordering, so unlike the values of an
+/// `RBTree` they can be changed in place.
+pub struct RBTreeMap<K: Ord, V> {
+    pub(super) tree: RBTree<Entry<K, V>>,
+}
+
+impl<K: Ord, V> RBTreeMap<K, V> {
+    pub fn new() -> RBTreeMap<K, V> {
+        RBTreeMap { tree: RBTree::new() }
+    }
+
+    pub fn len(&self) -> usize {
+        self.tree.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.tree.is_empty()
+    }
+
+    /// `key` can be any borrowed form of the keys that orders the same way, like `&str` for
+    /// `String` keys. The same goes for the other lookups.
+    pub fn contains_key<Q: ?Sized + Ord>(&self, key: &Q) -> bool
+    where
+        K: Borrow<Q>,
+    {
+        self.get(key).is_some()
+    }
+
+    pub fn get<Q: ?Sized + Ord>(&self, key: &Q) -> Option<&V>
+    where
+        K: Borrow<Q>,
+    {
+        self.tree.get_by(|entry| key.cmp(entry.key.borrow())).map(|entry| &entry.value)
+    }
+
+    /// The value of `key`, to be changed in place.
+    pub fn get_mut<Q: ?Sized + Ord>(&mut self, key: &Q) -> Option<&mut V>
+    where
+        K: Borrow<Q>,
+    {
+        self.tree.get_mut_by(|entry| key.cmp(entry.key.borrow())).map(|entry| &mut entry.value)
+    }
+
+    /// Maps `key` to `value`. Returns the value the key had before, the key itself is kept.
+    ///
+    /// The key is looked up before a new entry is inserted, so a new key walks down the tree
+    /// twice, O(log n) each time.
+    pub fn insert(&mut self, key: K, value: V) -> Option<V> {
+        if let Some(old) = self.get_mut(&key) {
+            return Some(core::mem::replace(old, value));
+        }
+        self.tree.insert(Entry { key, value });
+        None
+    }
+
+    /// Removes `key` and returns its value.
+    pub fn remove<Q: ?Sized + Ord>(&mut self, key: &Q) -> Option<V>
+    where
+        K: Borrow<Q>,
+    {
+        self.tree.take_by(|entry| key.cmp(entry.key.borrow())).map(|entry| entry.value)
+    }
+
+    /// The entries in order of their keys.
+    pub fn iter(&self) -> Iter<'_, K, V> {
+        Iter { entries: self.tree.iter() }
+    }
+
+    /// The entries in order of their keys, with values that can be changed in place.
+    pub fn iter_mut(&mut self) -> IterMut<'_, K, V> {
+        IterMut { entries: iter::IterMut::from_root(self.tree.root.as_deref_mut()) }
+    }
+
+    /// The values in order of their keys, to be changed in place.
+    pub fn values_mut(&mut self) -> ValuesMut<'_, K, V> {
+        ValuesMut { entries: iter::IterMut::from_root(self.tree.root.as_deref_mut()) }
+    }
+
+    /// The entries with keys inside `range`, in order, with values that can be changed in
+    /// place. Finding both ends takes O(log n).
+    pub fn range_mut<R: RangeBounds<K>>(&mut self, range: R) -> RangeMut<'_, K, V> {
+        RangeMut { entries: iter::IterMut::from_range(self.tree.root.as_deref_mut(), &range) }
+    }
+}
+
+impl<K: Ord, V> Default for RBTreeMap<K, V> {
+    fn default() -> RBTreeMap<K, V> {
+        RBTreeMap::new()
+    }
+}
+
+/// Later values replace earlier ones with the same key.
+impl<K: Ord, V> FromIterator<(K, V)> for RBTreeMap<K, V> {
+    fn from_iter<I: IntoIterator<Item = (K, V)>>(iter: I) -> Self {
+        let mut map = RBTreeMap::new();
+        for (key, value) in iter {
+            map.insert(key, value);
+        }
+        map
+    }
+}
+
+impl<K: Ord + fmt::Debug, V: fmt::Debug> fmt::Debug for RBTreeMap<K, V> {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.debug_map().entries(self.iter()).finish()
+    }
+}
+
+pub struct Iter<'a, K: Ord, V> {
+    entries: iter::Iter<'a, Entry<K, V>>,
+}
+
+impl<'a, K: Ord, V> Iterator for Iter<'a, K, V> {
+    type Item = (&'a K, &'a V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.entries.next().map(|entry| (&entry.key, &entry.value))
+    }
+}
+
+pub struct IterMut<'a, K: Ord, V> {
+    entries: iter::IterMut<'a, Entry<K, V>>,
+}
+
+impl<'a, K: Ord, V> Iterator for IterMut<'a, K, V> {
+    type Item = (&'a K, &'a mut V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.entries.next().map(|entry| (&entry.key, &mut entry.value))
+    }
+}
+
+impl<'a, K: Ord, V> DoubleEndedIterator for IterMut<'a, K, V> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.entries.next_back().map(|entry| (&entry.key, &mut entry.value))
+    }
+}
+
+pub struct ValuesMut<'a, K: Ord, V> {
+    entries: iter::IterMut<'a, Entry<K, V>>,
+}
+
+impl<'a, K: Ord, V> Iterator for ValuesMut<'a, K, V> {
+    type Item = &'a mut V;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.entries.next().map(|entry| &mut entry.value)
+    }
+}
+
+impl<'a, K: Ord, V> DoubleEndedIterator for ValuesMut<'a, K, V> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.entries.next_back().map(|entry| &mut entry.value)
+    }
+}
+
+pub struct RangeMut<'a, K: Ord, V> {
+    entries: iter::IterMut<'a, Entry<K, V>>,
+}
+
+impl<'a, K: Ord, V> Iterator for RangeMut<'a, K, V> {
+    type Item = (&'a K, &'a mut V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.entries.next().map(|entry| (&entry.key, &mut entry.value))
+    }
+}
+
+impl<'a, K: Ord, V> DoubleEndedIterator for RangeMut<'a, K, V> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        self.entries.next_back().map(|entry| (&entry.key, &mut entry.value))
+    }
+}
+
+pub struct IntoIter<K: Ord, V> {
+    entries: iter::IntoIter<Entry<K, V>>,
+}
+
+impl<K: Ord, V> Iterator for IntoIter<K, V> {
+    type Item = (K, V);
+
+    fn next(&mut self) -> Option<Self::Item> {
+        self.entries.next().map(|entry| (entry.key, entry.value))
+    }
+}
+
+impl<K: Ord, V> IntoIterator for RBTreeMap<K, V> {
+    type Item = (K, V);
+    type IntoIter = IntoIter<K, V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        IntoIter { entries: self.tree.into_iter() }
+    }
+}
+
+impl<'a, K: Ord, V> IntoIterator for &'a RBTreeMap<K, V> {
+    type Item = (&'a K, &'a V);
+    type IntoIter = Iter<'a, K, V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+impl<'a, K: Ord, V> IntoIterator for &'a mut RBTreeMap<K, V> {
+    type Item = (&'a K, &'a mut V);
+    type IntoIter = IterMut<'a, K, V>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter_mut()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use core::ops::Bound;
+
+    #[test]
+    fn test_insert_get_remove() {
+        let mut map = RBTreeMap::new();
+        assert_eq!(map.insert(3, "c"), None);
+        assert_eq!(map.insert(1, "a"), None);
+        assert_eq!(map.insert(3, "C"), Some("c"));
+
+        assert_eq!(map.len(), 2);
+        assert_eq!(map.get(&3), Some(&"C"));
+        assert!(!map.contains_key(&2));
+        assert_eq!(map.remove(&1), Some("a"));
+        assert_eq!(map.remove(&1), None);
+        assert_eq!(format!("{:?}", map), "{3: \"C\"}");
+    }
+
+    #[test]
+    fn test_borrowed_keys() {
+        let mut map: RBTreeMap<String, usize> = vec!["pear", "apple", "fig"].into_iter().map(|name| (name.to_string(), name.len())).collect();
+
+        assert!(map.contains_key("fig"));
+        assert_eq!(map.get("apple"), Some(&5));
+        *map.get_mut("pear").unwrap() += 1;
+        assert_eq!(map.remove("pear"), Some(5));
+        assert_eq!(map.remove("pear"), None);
+        assert_eq!(map.len(), 2);
+    }
+
+    #[test]
+    fn test_get_mut() {
+        let mut hits: RBTreeMap<&str, u32> = vec![("b", 0), ("a", 0), ("c", 0)].into_iter().collect();
+        for key in ["a", "c", "a", "d"].iter() {
+            if let Some(count) = hits.get_mut(key) {
+                *count += 1;
+            }
+        }
+
+        assert_eq!(hits.get_mut(&"d"), None);
+        assert_eq!(hits.into_iter().collect::<Vec<_>>(), vec![("a", 2), ("b", 0), ("c", 1)]);
+    }
+
+    #[test]
+    fn test_iter_mut_from_both_ends() {
+        let mut map: RBTreeMap<i32, i32> = (0..10).map(|key| (key, 0)).collect();
+        let mut entries = map.iter_mut();
+        let mut order = Vec::new();
+        while let Some((key, value)) = entries.next() {
+            *value = order.len() as i32;
+            order.push(*key);
+            if let Some((key, value)) = entries.next_back() {
+                *value = order.len() as i32;
+                order.push(*key);
+            }
+        }
+        assert_eq!(order, vec![0, 9, 1, 8, 2, 7, 3, 6, 4, 5]);
+
+        for value in map.values_mut().rev() {
+            *value *= 10;
+        }
+        for (_, value) in &mut map {
+            *value += 1;
+        }
+        let values: Vec<i32> = map.iter().map(|(_, value)| *value).collect();
+        assert_eq!(values, vec![1, 21, 41, 61, 81, 91, 71, 51, 31, 11]);
+    }
+
+    #[test]
+    fn test_range_mut() {
+        let mut map: RBTreeMap<i32, i32> = (0..100).map(|key| (key * 2, 0)).collect();
+        for (_, value) in map.range_mut(10..=20) {
+            *value += 1;
+        }
+        for (_, value) in map.range_mut(15..21).rev() {
+            *value += 1;
+        }
+        let changed: Vec<(i32, i32)> = map.iter().filter(|(_, value)| **value > 0).map(|(key, value)| (*key, *value)).collect();
+        assert_eq!(changed, vec![(10, 1), (12, 1), (14, 1), (16, 2), (18, 2), (20, 2)]);
+
+        assert_eq!(map.range_mut(..).count(), 100);
+        assert_eq!(map.range_mut(191..).map(|(key, _)| *key).collect::<Vec<i32>>(), vec![192, 194, 196, 198]);
+        assert_eq!(map.range_mut((Bound::Excluded(4), Bound::Excluded(8))).map(|(key, _)| *key).collect::<Vec<i32>>(), vec![6]);
+        assert_eq!(map.range_mut(7..7).count(), 0);
+        assert_eq!(map.range_mut(300..).count(), 0);
+    }
+}
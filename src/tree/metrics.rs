@@ -0,0 +1,146 @@
+#[cfg(feature = "metrics")]
+use core::sync::atomic::{AtomicUsize, Ordering};
+
+#[cfg(feature = "metrics")]
+use super::*;
+use super::trace::RotationKind;
+
+/// Operation counts of an `RBTree` since it was created or since `RBTree::reset_metrics`.
+///
+/// Inserts, deletes and lookups all count as operations. The depth of an operation is the
+/// number of nodes it visited on its way down.
+#[cfg(feature = "metrics")]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct Metrics {
+    pub operations: usize,
+    pub comparisons: usize,
+    pub single_rotations: usize,
+    pub double_rotations: usize,
+    pub recolors: usize,
+    pub total_depth: usize,
+    pub max_depth: usize,
+}
+
+#[cfg(feature = "metrics")]
+impl Metrics {
+    pub fn average_depth(&self) -> f64 {
+        if self.operations == 0 {
+            0.0
+        } else {
+            self.total_depth as f64 / self.operations as f64
+        }
+    }
+}
+
+// Atomic so that `contains` can count through a shared reference. Relaxed is enough,
+// the counters are not used to order anything else.
+#[cfg(feature = "metrics")]
+#[derive(Default)]
+pub(crate) struct Counters {
+    operations: AtomicUsize,
+    comparisons: AtomicUsize,
+    single_rotations: AtomicUsize,
+    double_rotations: AtomicUsize,
+    recolors: AtomicUsize,
+    total_depth: AtomicUsize,
+    max_depth: AtomicUsize,
+    // nodes visited by the insert or delete in progress
+    depth: AtomicUsize,
+}
+
+#[cfg(feature = "metrics")]
+impl Counters {
+    pub fn new() -> Counters {
+        Counters::default()
+    }
+
+    pub fn compared(&self, count: usize) {
+        self.comparisons.fetch_add(count, Ordering::Relaxed);
+    }
+
+    pub fn visited(&self) {
+        self.depth.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn rotated(&self, kind: RotationKind) {
+        match kind {
+            RotationKind::Single => self.single_rotations.fetch_add(1, Ordering::Relaxed),
+            RotationKind::Double => self.double_rotations.fetch_add(1, Ordering::Relaxed),
+        };
+    }
+
+    pub fn recolored(&self) {
+        self.recolors.fetch_add(1, Ordering::Relaxed);
+    }
+
+    // Ends an insert or delete, whose depth was counted with `visited`.
+    pub fn finish(&self) {
+        let depth = self.depth.swap(0, Ordering::Relaxed);
+        self.finish_with_depth(depth);
+    }
+
+    pub fn finish_with_depth(&self, depth: usize) {
+        self.operations.fetch_add(1, Ordering::Relaxed);
+        self.total_depth.fetch_add(depth, Ordering::Relaxed);
+        self.max_depth.fetch_max(depth, Ordering::Relaxed);
+    }
+
+    fn snapshot(&self) -> Metrics {
+        Metrics {
+            operations: self.operations.load(Ordering::Relaxed),
+            comparisons: self.comparisons.load(Ordering::Relaxed),
+            single_rotations: self.single_rotations.load(Ordering::Relaxed),
+            double_rotations: self.double_rotations.load(Ordering::Relaxed),
+            recolors: self.recolors.load(Ordering::Relaxed),
+            total_depth: self.total_depth.load(Ordering::Relaxed),
+            max_depth: self.max_depth.load(Ordering::Relaxed),
+        }
+    }
+
+    fn reset(&self) {
+        for counter in [&self.operations, &self.comparisons, &self.single_rotations, &self.double_rotations, &self.recolors, &self.total_depth, &self.max_depth] {
+            counter.store(0, Ordering::Relaxed);
+        }
+    }
+}
+
+// Without the feature every count compiles to nothing.
+#[cfg(not(feature = "metrics"))]
+pub(crate) struct Counters;
+
+#[cfg(not(feature = "metrics"))]
+impl Counters {
+    pub fn new() -> Counters {
+        Counters
+    }
+
+    #[inline(always)]
+    pub fn compared(&self, _count: usize) {}
+
+    #[inline(always)]
+    pub fn visited(&self) {}
+
+    #[inline(always)]
+    pub fn rotated(&self, _kind: RotationKind) {}
+
+    #[inline(always)]
+    pub fn recolored(&self) {}
+
+    #[inline(always)]
+    pub fn finish(&self) {}
+
+    #[inline(always)]
+    pub fn finish_with_depth(&self, _depth: usize) {}
+}
+
+#[cfg(feature = "metrics")]
+impl<T: Ord, A: Allocator> RBTree<T, A> {
+    /// The counts collected since the tree was created or last reset.
+    pub fn metrics(&self) -> Metrics {
+        self.observers.counters.snapshot()
+    }
+
+    pub fn reset_metrics(&self) {
+        self.observers.counters.reset();
+    }
+}
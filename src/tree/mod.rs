@@ -1,14 +1,61 @@
-use std::fmt;
+use allocator_api2::alloc::{Allocator, Global};
+use allocator_api2::boxed::Box;
+use alloc::vec::Vec;
+use core::borrow::Borrow;
+use core::fmt;
 
+mod bulk;
+mod display;
+mod dot;
+mod inspect;
 mod iter;
+mod llrb;
+pub mod map;
+mod metrics;
+mod nearest;
 mod node;
+mod observer;
+mod order;
+#[cfg(feature = "rayon")]
+mod rayon;
+#[cfg(feature = "serde")]
+mod serde;
+mod top_down;
+mod trace;
+mod transaction;
 mod utils;
+mod validate;
 
 use node::Node;
-use utils::{Color, get_color, Direction, RotationType};
+use observer::{rotate, set_color, Observers};
+use utils::{get_color, subtree_size, RotationType};
 
-pub struct RBTree<T: Ord> {
-    root: Option<Box<Node<T>>>,
+pub use bulk::ExtractIf;
+pub use display::Pretty;
+pub use inspect::{NodeRef, TreeStats};
+pub use iter::{IntoIter, Iter};
+pub use llrb::LLRBTree;
+pub use map::RBTreeMap;
+#[cfg(feature = "metrics")]
+pub use metrics::Metrics;
+pub use observer::Event;
+#[cfg(feature = "rayon")]
+pub use self::rayon::{IntoParIter, ParIter};
+pub use top_down::UpdateMode;
+pub use trace::{FixupCase, RotationKind, TraceEvent};
+pub use transaction::Transaction;
+pub use utils::{Color, Direction};
+pub use validate::InvariantViolation;
+pub(crate) use bulk::{red_depth, remove_range, Positional};
+pub(crate) use nearest::{k_nearest, nearest};
+pub(crate) use order::{count_while, range_positions, search, select, size, Compare, Position, SizedNode, Target};
+pub(crate) use validate::{validate_colored, validate_order, validate_sizes, ColoredNode, SearchNode};
+
+pub struct RBTree<T: Ord, A: Allocator = Global> {
+    root: Option<Box<Node<T, A>, A>>,
+    alloc: A,
+    observers: Observers<T>,
+    mode: UpdateMode,
 }
 
 enum InsertReturn {
@@ -18,123 +65,238 @@ enum InsertReturn {
     Rotate(RotationType),
 }
 
-enum DeleteReturn<T: Ord> {
+enum DeleteReturn<T: Ord, A: Allocator> {
     Done,
     NotFound,
     // Delete(possible replacement, checking done)
-    Delete(Option<Box<Node<T>>>, bool),
+    Delete(Option<Box<Node<T, A>, A>>, bool),
     Continue,
     Rotate(RotationType),
     Case3(Direction),
 }
 
 impl<T: Ord> RBTree<T> {
-
     pub fn new() -> RBTree<T> {
-        RBTree { root: None }
+        RBTree::new_in(Global)
+    }
+}
+
+impl<T: Ord, A: Allocator> RBTree<T, A> {
+    /// Creates an empty tree whose nodes are allocated with `alloc`.
+    pub fn new_in(alloc: A) -> RBTree<T, A> {
+        RBTree { root: None, alloc, observers: Observers::new(), mode: UpdateMode::BottomUp }
+    }
+
+    pub fn allocator(&self) -> &A {
+        &self.alloc
     }
 
     pub fn is_empty(&self) -> bool {
         self.root.is_none()
     }
 
+    pub fn iter(&self) -> iter::Iter<'_, T, A> {
+        iter::Iter::new(self)
+    }
+
     pub fn contains(&self, value: T) -> bool {
+        let counters = &self.observers.counters;
+        let mut depth = 0;
         let mut next = self.root.as_ref();
         while let Some(node) = next {
+            depth += 1;
             if node.value == value {
+                counters.compared(1);
+                counters.finish_with_depth(depth);
                 return true;
-            } else if value < node.value {
+            }
+            counters.compared(2);
+            if value < node.value {
                 next = node.left.as_ref();
             } else {
                 next = node.right.as_ref();
             }
         }
+        counters.finish_with_depth(depth);
         false
     }
 
+    /// The first value equal to `value` that a search finds, `value` can be any borrowed form
+    /// of the values that orders the same way.
+    pub fn get<Q: ?Sized + Ord>(&self, value: &Q) -> Option<&T>
+    where
+        T: Borrow<Q>,
+    {
+        self.get_by(|node_value| value.cmp(node_value.borrow()))
+    }
+
+    // Like `get`, with `compare` telling how the value looked for compares to a node's value.
+    // For lookups by something `T` can't borrow as, like the key of a map entry.
+    pub(crate) fn get_by<F: Fn(&T) -> core::cmp::Ordering>(&self, compare: F) -> Option<&T> {
+        search(self.root.as_deref(), compare)
+    }
+
+    // Like `get_by`, for callers inside the crate that only change what the ordering doesn't look at.
+    pub(crate) fn get_mut_by<F: Fn(&T) -> core::cmp::Ordering>(&mut self, compare: F) -> Option<&mut T> {
+        let mut next = self.root.as_deref_mut();
+        while let Some(node) = next {
+            match compare(&node.value) {
+                core::cmp::Ordering::Less => next = node.left.as_deref_mut(),
+                core::cmp::Ordering::Equal => return Some(&mut node.value),
+                core::cmp::Ordering::Greater => next = node.right.as_deref_mut(),
+            }
+        }
+        None
+    }
+}
+
+impl<T: Ord, A: Allocator + Clone> RBTree<T, A> {
     pub fn insert(&mut self, value: T) {
+        if self.mode == UpdateMode::TopDown && self.root.is_some() {
+            return self.insert_top_down(value);
+        }
+        self.insert_bottom_up(value);
+    }
+
+    // Transactions rebalance bottom-up whatever the mode, their undo log relies on it.
+    pub(super) fn insert_bottom_up(&mut self, value: T) {
         if self.root.is_none() {
-            self.root = Some(Box::new(Node {
-                color: Color::Black,
-                value: value,
-                left: None,
-                right: None,
-            }));
+            let root = self.root.insert(Box::new_in(Node::new(Color::Black, value), self.alloc.clone()));
+            self.observers.notify(Event::Insert(&root.value));
+            self.observers.counters.finish();
             return;
         }
-        let insert_result = Self::recursive_insert(self.root.as_mut().unwrap(), value);
+        let new_node = Box::new_in(Node::new(Color::Red, value), self.alloc.clone());
+        let observers = &mut self.observers;
+        let insert_result = Self::recursive_insert(self.root.as_mut().unwrap(), new_node, observers);
         match insert_result {
             InsertReturn::Done => {},
-            InsertReturn::Node => {},
-            InsertReturn::Parent(_) => {
-                self.root.as_mut().unwrap().color = Color::Black;
+            // the root was recolored red or has a red child, it can always be made black
+            InsertReturn::Node | InsertReturn::Parent(_) => {
+                observers.fixup(FixupCase::InsertRedRoot);
+                set_color(self.root.as_mut().unwrap(), Color::Black, observers);
             },
             InsertReturn::Rotate(rotation_type) => {
                 let rotation_dir = rotation_type.get_direction();
-                let old_root = *(self.root.take().unwrap());
-                let mut new_root = old_root.rotate(rotation_type);
-                new_root.color = Color::Black;
-                new_root.get_child(rotation_dir).expect("The parent should have been rotated here").color = Color::Red;
-                self.root = Some(Box::new(new_root));
+                let old_root = self.root.take().unwrap();
+                let mut new_root = rotate(old_root, rotation_type, observers);
+                set_color(&mut new_root, Color::Black, observers);
+                set_color(new_root.get_child(rotation_dir).expect("The parent should have been rotated here"), Color::Red, observers);
+                self.root = Some(new_root);
             }
         }
+        observers.counters.finish();
     }
 
     pub fn delete(&mut self, value: T) -> bool {
-        if self.root.is_none() {
-            return false;
+        self.take(&value).is_some()
+    }
+
+    /// Removes a value equal to `value` and returns the one that was stored in the tree.
+    /// `value` can be any borrowed form of the values, as long as it orders the same way.
+    pub fn take<Q: ?Sized + Ord>(&mut self, value: &Q) -> Option<T>
+    where
+        T: Borrow<Q>,
+    {
+        if self.mode == UpdateMode::TopDown {
+            return self.take_top_down(value);
         }
-        let delete_result = Self::recursive_delete(self.root.as_mut().unwrap(), value);
+        self.take_bottom_up(value)
+    }
+
+    pub(super) fn take_bottom_up<Q: ?Sized + Ord>(&mut self, mut value: &Q) -> Option<T>
+    where
+        T: Borrow<Q>,
+    {
+        self.take_target(&mut value)
+    }
+
+    // Removes the value at `index` in sorted order, rebalancing bottom-up.
+    pub(super) fn take_at(&mut self, index: usize) -> Option<T> {
+        self.take_target(&mut Position(index))
+    }
+
+    // Like `get_by` for `take`, rebalancing bottom-up.
+    pub(crate) fn take_by<F: Fn(&T) -> core::cmp::Ordering>(&mut self, compare: F) -> Option<T> {
+        self.take_target(&mut Compare(compare))
+    }
+
+    fn take_target(&mut self, target: &mut impl Target<T>) -> Option<T> {
+        let observers = &mut self.observers;
+        let root = match self.root.as_mut() {
+            Some(root) => root,
+            None => {
+                observers.counters.finish();
+                return None;
+            },
+        };
+        let mut removed = None;
+        let delete_result = Self::recursive_delete(root, target, &mut removed, observers);
         match delete_result {
-            DeleteReturn::Done => true,
-            // case 2
-            DeleteReturn::Continue => true,
-            DeleteReturn::NotFound => false,
-            DeleteReturn::Delete(replacement, _) => {
+            DeleteReturn::Done | DeleteReturn::NotFound => {},
+            // case 2, nothing to fix at the root
+            DeleteReturn::Continue => observers.fixup(FixupCase::DeleteReachedRoot),
+            DeleteReturn::Delete(mut replacement, _) => {
+                let old_root = self.root.take();
+                removed = old_root.map(|node| Box::into_inner(node).value);
+                if let Some(value) = &removed {
+                    observers.notify(Event::Delete(value));
+                }
+                if let Some(ref mut new_root) = replacement {
+                    set_color(new_root, Color::Black, observers);
+                }
                 self.root = replacement;
-                true
             }
             DeleteReturn::Rotate(rotation_type) => {
                 let old_root = self.root.take().unwrap();
                 let old_parent_color = old_root.color;
-                let mut new_root = old_root.rotate(rotation_type);
-                new_root.color = old_parent_color;
+                let mut new_root = rotate(old_root, rotation_type, observers);
+                set_color(&mut new_root, old_parent_color, observers);
                 if let Some(ref mut left_child) = new_root.left {
-                    left_child.color = Color::Black;
+                    set_color(left_child, Color::Black, observers);
                 }
                 if let Some(ref mut right_child) = new_root.right {
-                    right_child.color = Color::Black;
+                    set_color(right_child, Color::Black, observers);
                 }
-                self.root = Some(Box::new(new_root));
-                true
+                self.root = Some(new_root);
             },
             DeleteReturn::Case3(direction) => {
-                let old_root = *(self.root.take().unwrap());
-                let new_root = Self::case3(old_root, direction);
-                self.root = Some(Box::new(new_root));
-                true
+                let old_root = self.root.take().unwrap();
+                let new_root = Self::case3(old_root, direction, observers);
+                self.root = Some(new_root);
             }
         }
+        observers.counters.finish();
+        removed
     }
 
-    fn recursive_insert(node: &mut Node<T>, value: T) -> InsertReturn {
-        let direction = if value < node.value { Direction::Left } else { Direction::Right };
+    fn recursive_insert(node: &mut Node<T, A>, new_node: Box<Node<T, A>, A>, observers: &mut Observers<T>) -> InsertReturn {
+        observers.visit(&node.value);
+        observers.counters.compared(1);
+        let direction = if new_node.value < node.value { Direction::Left } else { Direction::Right };
         let uncle_color = get_color(node.get_child_as_ref(direction.opposite()));
+        // the new node always ends up below, rotations take the size over
+        node.size += 1;
         let mut next = node.get_child(direction);
         if next.is_none() {
-            node.set_child(direction, Node::<T>::new(Color::Red, value));
-            
+            node.set_child(direction, new_node);
+            observers.notify(Event::Insert(&node.child(direction).unwrap().value));
+
             return match node.color {
-                Color::Black => InsertReturn::Done,
+                Color::Black => {
+                    observers.fixup(FixupCase::InsertBlackParent);
+                    InsertReturn::Done
+                },
                 Color::Red => InsertReturn::Parent(direction),
             };
         }
     
-        let state = Self::recursive_insert(next.as_mut().unwrap(), value);
+        let state = Self::recursive_insert(next.as_mut().unwrap(), new_node, observers);
         match state {
             InsertReturn::Done => InsertReturn::Done,
             InsertReturn::Node => {
                 if node.color == Color::Black {
+                    observers.fixup(FixupCase::InsertBlackParent);
                     InsertReturn::Done
                 } else {
                     InsertReturn::Parent(direction)
@@ -142,26 +304,29 @@ impl<T: Ord> RBTree<T> {
             },
             InsertReturn::Parent(child_direction) => {
                 if uncle_color == Color::Red {
-                    next.unwrap().color = Color::Black;
-                    node.get_child(direction.opposite()).unwrap().color = Color::Black;
-                    node.color = Color::Red;
+                    observers.fixup(FixupCase::InsertRedUncle);
+                    set_color(next.unwrap(), Color::Black, observers);
+                    set_color(node.get_child(direction.opposite()).unwrap(), Color::Black, observers);
+                    set_color(node, Color::Red, observers);
                     InsertReturn::Node
                 } else {
                     // case 4 & 5, inner grandchild
                     if child_direction != direction {
+                        observers.fixup(FixupCase::InsertInnerGrandchild);
                         InsertReturn::Rotate(RotationType::Double(direction.opposite()))
                     // case 5
                     } else {
+                        observers.fixup(FixupCase::InsertOuterGrandchild);
                         InsertReturn::Rotate(RotationType::Single(direction.opposite()))
                     }
                 }
             },
             InsertReturn::Rotate(rotation_type) => {
                 let rotation_dir = rotation_type.get_direction();
-                let child = *(node.remove_child(direction).take().unwrap());
-                let mut rotated_node = child.rotate(rotation_type);
-                rotated_node.color = Color::Black;
-                rotated_node.get_child(rotation_dir).expect("The parent should have been rotated here").color = Color::Red;
+                let child = node.remove_child(direction).unwrap();
+                let mut rotated_node = rotate(child, rotation_type, observers);
+                set_color(&mut rotated_node, Color::Black, observers);
+                set_color(rotated_node.get_child(rotation_dir).expect("The parent should have been rotated here"), Color::Red, observers);
                 
                 node.set_child(direction, rotated_node);
                 InsertReturn::Done
@@ -169,13 +334,17 @@ impl<T: Ord> RBTree<T> {
         }
     }
     
-    fn recursive_delete(node: &mut Box<Node<T>>, value: T) -> DeleteReturn<T> {
-        if value == node.value {
+    // The value of the removed node is moved into `removed` once the node is detached.
+    fn recursive_delete(node: &mut Node<T, A>, target: &mut impl Target<T>, removed: &mut Option<T>, observers: &mut Observers<T>) -> DeleteReturn<T, A> {
+        observers.visit(&node.value);
+        let ordering = target.locate(&node.value, subtree_size(node.left.as_deref()));
+        let found = ordering == core::cmp::Ordering::Equal;
+        observers.counters.compared(if found { 1 } else { 2 });
+        if found {
             if node.left.is_some() && node.right.is_some() {
-                let (delete_return, value) = Self::successor_stage_delete(node.right.as_mut().unwrap());
                 // successor value moved here, the successor node is deleted
-                node.value = value;
-                Self::handle_delete_return(node, Direction::Right, delete_return)
+                let delete_return = Self::successor_stage_delete(node.right.as_mut().unwrap(), &mut node.value, removed, observers);
+                Self::handle_delete_return(node, Direction::Right, delete_return, removed, observers)
             } else if node.color == Color::Red {
                 DeleteReturn::Delete(None, true)
             } else if node.left.is_some() {
@@ -185,19 +354,19 @@ impl<T: Ord> RBTree<T> {
             } else {
                 DeleteReturn::Delete(None, false)
             }
-        } else if value < node.value {
+        } else if ordering == core::cmp::Ordering::Less {
             match node.left.as_mut() {
                 Some(left_child) => {
-                    let delete_return = Self::recursive_delete(left_child, value);
-                    Self::handle_delete_return(node, Direction::Left, delete_return)
+                    let delete_return = Self::recursive_delete(left_child, target, removed, observers);
+                    Self::handle_delete_return(node, Direction::Left, delete_return, removed, observers)
                 },
                 None => DeleteReturn::NotFound,
             }
         } else {
             match node.right.as_mut() {
                 Some(right_child) => {
-                    let delete_return = Self::recursive_delete(right_child, value);
-                    Self::handle_delete_return(node, Direction::Right, delete_return)
+                    let delete_return = Self::recursive_delete(right_child, target, removed, observers);
+                    Self::handle_delete_return(node, Direction::Right, delete_return, removed, observers)
                 },
                 None => DeleteReturn::NotFound,
             }
@@ -205,81 +374,97 @@ impl<T: Ord> RBTree<T> {
     
     }
     
-    fn successor_stage_delete(node: &mut Box<Node<T>>) -> (DeleteReturn<T>, T) {
+    fn successor_stage_delete(node: &mut Node<T, A>, target: &mut T, removed: &mut Option<T>, observers: &mut Observers<T>) -> DeleteReturn<T, A> {
+        observers.visit(&node.value);
         match node.left.as_mut() {
             Some(left_child) => {
-                let (delete_return, value) = Self::successor_stage_delete(left_child);
-                (Self::handle_delete_return(node, Direction::Left, delete_return), value)
+                let delete_return = Self::successor_stage_delete(left_child, target, removed, observers);
+                Self::handle_delete_return(node, Direction::Left, delete_return, removed, observers)
             },
             None => {
-                let value = node.value;
+                // the deleted value ends up in this node, which is removed
+                core::mem::swap(&mut node.value, target);
                 if node.color == Color::Red {
-                    (DeleteReturn::Delete(None, true), value)
+                    DeleteReturn::Delete(None, true)
                 } else if node.right.is_some() {
-                    (DeleteReturn::Delete(node.right.take(), true), value)
+                    DeleteReturn::Delete(node.right.take(), true)
                 } else {
-                    (DeleteReturn::Delete(None, false), value)
+                    DeleteReturn::Delete(None, false)
                 }
             }
         }
     }
     
-    fn handle_delete_return(node: &mut Box<Node<T>>, dir: Direction, state: DeleteReturn<T>) -> DeleteReturn<T> {
+    fn handle_delete_return(
+        node: &mut Node<T, A>,
+        dir: Direction,
+        state: DeleteReturn<T, A>,
+        removed: &mut Option<T>,
+        observers: &mut Observers<T>,
+    ) -> DeleteReturn<T, A> {
+        if !matches!(state, DeleteReturn::NotFound) {
+            node.size -= 1;
+        }
         match state {
             DeleteReturn::NotFound => DeleteReturn::NotFound,
             DeleteReturn::Done => DeleteReturn::Done,
-            DeleteReturn::Continue => Self::do_delete_checks(node, dir),
+            DeleteReturn::Continue => Self::do_delete_checks(node, dir, observers),
             DeleteReturn::Rotate(rotation_type) => {
                 let child = node.remove_child(dir).unwrap();
                 let old_parent_color = child.color;
-                let mut rotated = child.rotate(rotation_type);
-                rotated.color = old_parent_color;
+                let mut rotated = rotate(child, rotation_type, observers);
+                set_color(&mut rotated, old_parent_color, observers);
                 if let Some(ref mut left_node) = rotated.left {
-                    left_node.color = Color::Black;
+                    set_color(left_node, Color::Black, observers);
                 }
                 if let Some(ref mut right_node) = rotated.right {
-                    right_node.color = Color::Black;
+                    set_color(right_node, Color::Black, observers);
                 }
                 node.set_child(dir, rotated);
                 DeleteReturn::Done
             },
             DeleteReturn::Delete(mut replacing_node, done) => {
-                if let Some(ref mut node) = replacing_node {
-                    node.color = Color::Black;
+                let old_child = node.remove_child(dir);
+                *removed = old_child.map(|child| Box::into_inner(child).value);
+                if let Some(value) = removed.as_ref() {
+                    observers.notify(Event::Delete(value));
+                }
+                if let Some(ref mut replacing) = replacing_node {
+                    set_color(replacing, Color::Black, observers);
                 }
                 node.set_child_or_leaf(dir, replacing_node);
                 if done {
                     DeleteReturn::Done
                 } else {
-                    Self::do_delete_checks(node, dir)
+                    Self::do_delete_checks(node, dir, observers)
                 }
             },
             DeleteReturn::Case3(direction) => {
-                let child = *(node.remove_child(dir).unwrap());
-                let rotated = Self::case3(child, direction);
+                let child = node.remove_child(dir).unwrap();
+                let rotated = Self::case3(child, direction, observers);
                 node.set_child(dir, rotated);
                 DeleteReturn::Done
             }
         }
     }
     
-    fn case3(child: Node<T>, direction: Direction) -> Node<T> {
-        let mut rotated = child.rotate(RotationType::Single(direction));
-        rotated.color = Color::Black;
-        rotated.get_child(direction).unwrap().color = Color::Red;
-        let next_step = Self::do_delete_checks(rotated.get_child(direction).unwrap(), direction);
+    fn case3(child: Box<Node<T, A>, A>, direction: Direction, observers: &mut Observers<T>) -> Box<Node<T, A>, A> {
+        let mut rotated = rotate(child, RotationType::Single(direction), observers);
+        set_color(&mut rotated, Color::Black, observers);
+        set_color(rotated.get_child(direction).unwrap(), Color::Red, observers);
+        let next_step = Self::do_delete_checks(rotated.get_child(direction).unwrap(), direction, observers);
         match next_step {
             DeleteReturn::Done => {},
             DeleteReturn::Rotate(second_rotation) => {
-                let foobar = *(rotated.remove_child(direction).unwrap());
-                let mut new_foo = foobar.rotate(second_rotation);
+                let foobar = rotated.remove_child(direction).unwrap();
+                let mut new_foo = rotate(foobar, second_rotation, observers);
                 // old parent color is red in this case
-                new_foo.color = Color::Red;
+                set_color(&mut new_foo, Color::Red, observers);
                 if let Some(ref mut left) = new_foo.left {
-                    left.color = Color::Black;
+                    set_color(left, Color::Black, observers);
                 }
                 if let Some(ref mut right) = new_foo.right {
-                    right.color = Color::Black;
+                    set_color(right, Color::Black, observers);
                 }
                 rotated.set_child(direction, new_foo);
             },
@@ -288,15 +473,15 @@ impl<T: Ord> RBTree<T> {
         rotated
     }
     
-    fn do_delete_checks(parent: &mut Box<Node<T>>, dir: Direction) -> DeleteReturn<T> {
+    fn do_delete_checks(parent: &mut Node<T, A>, dir: Direction, observers: &mut Observers<T>) -> DeleteReturn<T, A> {
         let parent_is_black = parent.is_black();
         let node_is_black = get_color(parent.get_child_as_ref(dir)) == Color::Black;
         let sibling = parent.get_child(dir.opposite())
             .expect("Broken invariant: delete checks happen on the path up from a (former) black node. There can not be any leaves on such a path (except at the very end).");
         let sibling_is_black = sibling.is_black();
         
-        let left_nephew_is_black = get_color(sibling.left.as_ref()) == Color::Black;
-        let right_nephew_is_black = get_color(sibling.right.as_ref()) == Color::Black;
+        let left_nephew_is_black = get_color(sibling.left.as_deref()) == Color::Black;
+        let right_nephew_is_black = get_color(sibling.right.as_deref()) == Color::Black;
         let all_black = parent_is_black && node_is_black && sibling_is_black && left_nephew_is_black && right_nephew_is_black;
         // from siblings point of view. Sibling is on the opposite side
         let distant_nephew_is_red = match dir.opposite() {
@@ -306,38 +491,58 @@ impl<T: Ord> RBTree<T> {
     
         if all_black {
             // case 1
-            sibling.color = Color::Red;
+            observers.fixup(FixupCase::DeleteAllBlack);
+            set_color(sibling, Color::Red, observers);
             DeleteReturn::Continue
         } else if !sibling_is_black {
             // case 3
+            observers.fixup(FixupCase::DeleteRedSibling);
             DeleteReturn::Case3(dir)
         } else if !parent_is_black && sibling_is_black && left_nephew_is_black && right_nephew_is_black {
             // case 4
-            sibling.color = Color::Red;
-            parent.color = Color::Black;
+            observers.fixup(FixupCase::DeleteRedParent);
+            set_color(sibling, Color::Red, observers);
+            set_color(parent, Color::Black, observers);
             DeleteReturn::Done
         } else if distant_nephew_is_red {
             //case 6
+            observers.fixup(FixupCase::DeleteDistantNephew);
             DeleteReturn::Rotate(RotationType::Single(dir))
         } else {
             // case 5 (+6)
+            observers.fixup(FixupCase::DeleteCloseNephew);
             DeleteReturn::Rotate(RotationType::Double(dir))
         }
     }
     
 }
 
-impl<T: Ord> IntoIterator for RBTree<T> {
+impl<T: Ord, A: Allocator + Clone + Default> Default for RBTree<T, A> {
+    fn default() -> RBTree<T, A> {
+        RBTree::new_in(A::default())
+    }
+}
+
+impl<T: Ord, A: Allocator> IntoIterator for RBTree<T, A> {
     type Item = T;
-    type IntoIter = iter::IntoIter<T>;
+    type IntoIter = iter::IntoIter<T, A>;
 
     fn into_iter(self) -> Self::IntoIter {
-        iter::IntoIter::<T>::new(self)
+        iter::IntoIter::<T, A>::new(self)
+    }
+}
+
+impl<'a, T: Ord, A: Allocator> IntoIterator for &'a RBTree<T, A> {
+    type Item = &'a T;
+    type IntoIter = iter::Iter<'a, T, A>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
     }
 }
 
 // helper function for fmt::Debug
-fn fmt_subtree<T: Ord + fmt::Debug>(node: &Box<Node<T>>, formatter: &mut fmt::Formatter, indent: usize) -> fmt::Result {
+fn fmt_subtree<T: Ord + fmt::Debug, A: Allocator>(node: &Node<T, A>, formatter: &mut fmt::Formatter, indent: usize) -> fmt::Result {
     let indent_size = 2;
     formatter.write_fmt(format_args!("{:width$} {:?} {:?}\n", "", node.color, node.value, width=indent))?;
 
@@ -355,10 +560,10 @@ fn fmt_subtree<T: Ord + fmt::Debug>(node: &Box<Node<T>>, formatter: &mut fmt::Fo
     }
 }
 
-impl<T: Ord + fmt::Debug> fmt::Debug for RBTree<T> {
+impl<T: Ord + fmt::Debug, A: Allocator> fmt::Debug for RBTree<T, A> {
     fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
         match &self.root {
-            Some(root_node) => fmt_subtree::<T>(root_node, formatter, 0),
+            Some(root_node) => fmt_subtree(root_node, formatter, 0),
             None => formatter.write_str("Empty tree\n"),
         }
     }
@@ -369,38 +574,87 @@ impl<T: Ord + fmt::Debug> fmt::Debug for RBTree<T> {
 mod tests {
     use super::*;
 
+    mod differential;
+
     mod tools {
         use super::super::*;
 
+        // Ordered by the first field only, so copies that are equal can still be told apart.
+        #[derive(Debug)]
+        pub struct Tagged(pub i32, pub usize);
+
+        impl PartialEq for Tagged {
+            fn eq(&self, other: &Self) -> bool {
+                self.0 == other.0
+            }
+        }
+
+        impl Eq for Tagged {}
+
+        impl PartialOrd for Tagged {
+            fn partial_cmp(&self, other: &Self) -> Option<core::cmp::Ordering> {
+                Some(self.cmp(other))
+            }
+        }
+
+        impl Ord for Tagged {
+            fn cmp(&self, other: &Self) -> core::cmp::Ordering {
+                self.0.cmp(&other.0)
+            }
+        }
+
+        // The labels of the values and the colors in preorder, `None` for every leaf.
+        pub fn shape<T: Ord, L>(tree: &RBTree<T>, label: impl Fn(&T) -> L + Copy) -> Vec<Option<(L, Color)>> {
+            fn walk<T: Ord, L>(node: Option<NodeRef<'_, T>>, label: impl Fn(&T) -> L + Copy, shape: &mut Vec<Option<(L, Color)>>) {
+                match node {
+                    Some(node) => {
+                        shape.push(Some((label(node.value()), node.color())));
+                        walk(node.left(), label, shape);
+                        walk(node.right(), label, shape);
+                    },
+                    None => shape.push(None),
+                }
+            }
+            let mut shape = Vec::new();
+            walk(tree.root(), label, &mut shape);
+            shape
+        }
+
+        // A tree around a hand-built root, for fixtures that break the invariants on purpose.
+        pub fn tree_from_root<T: Ord>(root: Node<T>) -> RBTree<T> {
+            let mut tree = RBTree::new();
+            tree.root = Some(Box::new(root));
+            tree
+        }
+
         pub fn assert_no_red_violations<T: Ord>(tree: &RBTree<T>) {
-            match &tree.root {
-                Some(node) => check_red_violations(&node),
-                None => {},
+            if let Some(node) = &tree.root {
+                check_red_violations(node);
             }
         }
 
-        fn check_red_violations<T: Ord>(node: &Box<Node<T>>) {
+        fn check_red_violations<T: Ord>(node: &Node<T>) {
             if node.color == Color::Red {
-                assert_eq!(get_color(node.left.as_ref()), Color::Black, "Child of red node must be black");
-                assert_eq!(get_color(node.right.as_ref()), Color::Black, "Child of red node must be black");
+                assert_eq!(get_color(node.left.as_deref()), Color::Black, "Child of red node must be black");
+                assert_eq!(get_color(node.right.as_deref()), Color::Black, "Child of red node must be black");
             }
 
             if let Some(left_node) = node.left.as_ref() {
                 check_red_violations(left_node);
             }
             if let Some(right_node) = node.right.as_ref() {
-                check_red_violations(&right_node);
+                check_red_violations(right_node);
             }
         }
 
         pub fn assert_no_black_violations<T: Ord + fmt::Debug>(tree: &RBTree<T>) {
-            check_black_violations(tree.root.as_ref());
+            check_black_violations(tree.root.as_deref());
         }
 
-        fn check_black_violations<T: Ord + fmt::Debug>(node_or_leaf: Option<&Box<Node<T>>>) -> i32 {
+        fn check_black_violations<T: Ord + fmt::Debug>(node_or_leaf: Option<&Node<T>>) -> i32 {
             if let Some(node) = node_or_leaf {
-                let black_height_left = check_black_violations(node.left.as_ref());
-                let black_height_right = check_black_violations(node.right.as_ref());
+                let black_height_left = check_black_violations(node.left.as_deref());
+                let black_height_right = check_black_violations(node.right.as_deref());
                 
                 assert_eq!(black_height_left, black_height_right, "Paths to leaves must contain same amount of black nodes. Violations in subtree of {:?} node with value {:?}", node.color, node.value);
                 
@@ -414,12 +668,12 @@ mod tests {
         }
 
         pub fn assert_tree_size<T: Ord + fmt::Debug>(tree: &RBTree<T>, expected_size: usize) {
-            assert_eq!(subtree_size(tree.root.as_ref()), expected_size, "RBTree was not the right size");
+            assert_eq!(subtree_size(tree.root.as_deref()), expected_size, "RBTree was not the right size");
         }
 
-        fn subtree_size<T: Ord>(node_or_leaf: Option<&Box<Node<T>>>) -> usize {
+        fn subtree_size<T: Ord>(node_or_leaf: Option<&Node<T>>) -> usize {
             match node_or_leaf {
-                Some(node) => subtree_size(node.left.as_ref()) + subtree_size(node.right.as_ref()) + 1,
+                Some(node) => subtree_size(node.left.as_deref()) + subtree_size(node.right.as_deref()) + 1,
                 None => 0,
             }
         }
@@ -439,24 +693,25 @@ mod tests {
 
     #[test]
     fn test_contains() {
-        let t = RBTree::<i32> {
-            root: Some(Box::new(Node::<i32> {
+        let t = tools::tree_from_root(Node::<i32> {
+            color: Color::Red,
+            value: 5,
+            left: Some(Box::new(Node::<i32> {
                 color: Color::Red,
-                value: 5,
-                left: Some(Box::new(Node::<i32> {
-                    color: Color::Red,
-                    value: 3,
-                    left: Some(Box::new(Node::<i32> { color: Color::Red, value: 1, left: None, right: None })),
-                    right: Some(Box::new(Node::<i32> { color: Color::Red, value: 4, left: None, right: None })),
-                })),
-                right: Some(Box::new(Node::<i32> {
-                    color: Color::Red,
-                    value: 8,
-                    left: Some(Box::new(Node::<i32> { color: Color::Red, value: 6, left: None, right: None})),
-                    right: None,
-                })),
+                value: 3,
+                left: Some(Box::new(Node::<i32> { color: Color::Red, value: 1, left: None, right: None, size: 1 })),
+                right: Some(Box::new(Node::<i32> { color: Color::Red, value: 4, left: None, right: None, size: 1 })),
+                size: 3,
             })),
-        };
+            right: Some(Box::new(Node::<i32> {
+                color: Color::Red,
+                value: 8,
+                left: Some(Box::new(Node::<i32> { color: Color::Red, value: 6, left: None, right: None, size: 1 })),
+                right: None,
+                size: 2,
+            })),
+            size: 6,
+        });
         assert!(t.contains(5));
         assert!(t.contains(6));
         assert!(t.contains(1));
@@ -561,7 +816,7 @@ mod tests {
     fn test_delete_all_then_insert() {
         let mut tree = RBTree::<i32>::new();
         assert!(!tree.delete(8));
-        let v = vec![134, 75, 13, 54, 9, 134, 4];
+        let v = [134, 75, 13, 54, 9, 134, 4];
         for i in v.iter() {
             tree.insert(*i);
         }
@@ -577,4 +832,870 @@ mod tests {
 
         tools::assert_tree_size(&tree, 3);
     }
+
+    #[test]
+    fn test_retain() {
+        let mut tree = RBTree::<i32>::new();
+        for i in 0..1000 {
+            tree.insert(i % 250);
+        }
+
+        tree.retain(|value| value % 3 == 0);
+
+        tools::assert_tree_size(&tree, 84 * 4);
+        tools::assert_no_red_violations(&tree);
+        tools::assert_no_black_violations(&tree);
+        assert!(tree.contains(249));
+        assert!(!tree.contains(248));
+    }
+
+    #[test]
+    fn test_drain() {
+        let mut tree = RBTree::<i32>::new();
+        for i in [5, 3, 9, 1, 7] {
+            tree.insert(i);
+        }
+
+        assert_eq!(tree.drain().collect::<Vec<i32>>(), vec![1, 3, 5, 7, 9]);
+        assert!(tree.is_empty());
+        tree.insert(2);
+        tools::assert_tree_size(&tree, 1);
+    }
+
+    #[test]
+    fn test_extract_if() {
+        let mut tree = RBTree::<i32>::new();
+        for i in (0..100).rev() {
+            tree.insert(i);
+        }
+
+        let extracted: Vec<i32> = tree.extract_if(|value| value % 10 == 0).collect();
+
+        assert_eq!(extracted, vec![0, 10, 20, 30, 40, 50, 60, 70, 80, 90]);
+        tools::assert_tree_size(&tree, 90);
+        tools::assert_no_red_violations(&tree);
+        tools::assert_no_black_violations(&tree);
+
+        // values after the last one taken stay in the tree
+        let mut odd = tree.extract_if(|value| value % 2 == 1);
+        assert_eq!(odd.next(), Some(1));
+        assert_eq!(odd.next(), Some(3));
+        assert_eq!(tree.len(), 88);
+        assert!(tree.contains(5));
+        assert_eq!(tree.validate(), Ok(()));
+    }
+
+    #[test]
+    fn test_remove_range() {
+        let mut tree = RBTree::<i32>::new();
+        for i in 0..100 {
+            tree.insert(i);
+        }
+
+        assert_eq!(tree.remove_range(20..30), 10);
+        assert_eq!(tree.remove_range(..=4), 5);
+        assert_eq!(tree.remove_range(95..), 5);
+        assert_eq!(tree.remove_range(200..), 0);
+
+        tools::assert_tree_size(&tree, 80);
+        tools::assert_no_red_violations(&tree);
+        tools::assert_no_black_violations(&tree);
+        assert!(!tree.contains(25));
+        assert!(tree.contains(30));
+        assert!(tree.delete(50));
+        tools::assert_no_black_violations(&tree);
+    }
+
+    #[test]
+    fn test_rebuild_keeps_invariants_for_every_size() {
+        for len in 0..70 {
+            let mut tree = RBTree::<i32>::new();
+            for i in 0..len {
+                tree.insert(i);
+            }
+            tree.retain(|_| true);
+
+            tools::assert_tree_size(&tree, len as usize);
+            tools::assert_no_red_violations(&tree);
+            tools::assert_no_black_violations(&tree);
+        }
+    }
+
+    #[test]
+    fn test_validate_accepts_valid_trees() {
+        let mut tree = RBTree::<i32>::new();
+        assert_eq!(tree.validate(), Ok(()));
+        for i in 0..500 {
+            tree.insert((i * 37) % 101);
+            assert_eq!(tree.validate(), Ok(()));
+        }
+        for i in 0..300 {
+            tree.delete((i * 13) % 101);
+            assert_eq!(tree.validate(), Ok(()));
+        }
+    }
+
+    #[test]
+    fn test_validate_red_root() {
+        let tree = tools::tree_from_root(Node::new(Color::Red, 1));
+        assert_eq!(tree.validate(), Err(InvariantViolation::RedRoot { value: &1 }));
+    }
+
+    #[test]
+    fn test_validate_red_red() {
+        let mut red = Node::new(Color::Red, 4);
+        red.left = Some(Box::new(Node::new(Color::Red, 3)));
+        let mut root = Node::new(Color::Black, 5);
+        root.left = Some(Box::new(red));
+        root.right = Some(Box::new(Node::new(Color::Black, 8)));
+        let tree = tools::tree_from_root(root);
+
+        assert_eq!(tree.validate(), Err(InvariantViolation::RedRed { value: &3, path: vec![Direction::Left, Direction::Left] }));
+    }
+
+    #[test]
+    fn test_validate_black_height() {
+        let mut root = Node::new(Color::Black, 5);
+        root.right = Some(Box::new(Node::new(Color::Black, 8)));
+        let tree = tools::tree_from_root(root);
+
+        assert_eq!(tree.validate(), Err(InvariantViolation::BlackHeight { value: &5, path: vec![], left: 0, right: 1 }));
+    }
+
+    #[test]
+    fn test_validate_order() {
+        let mut left = Node::new(Color::Black, 2);
+        left.right = Some(Box::new(Node::new(Color::Red, 7)));
+        let mut root = Node::new(Color::Black, 5);
+        root.left = Some(Box::new(left));
+        root.right = Some(Box::new(Node::new(Color::Black, 8)));
+        let tree = tools::tree_from_root(root);
+
+        let violation = tree.validate().unwrap_err();
+        assert_eq!(violation, InvariantViolation::Order { value: &7, path: vec![Direction::Left, Direction::Right] });
+        assert_eq!(violation.to_string(), "node 7 at [Left, Right] is out of order");
+    }
+
+    #[test]
+    fn test_shape_of_empty_tree() {
+        let tree = RBTree::<i32>::new();
+        assert!(tree.root().is_none());
+        assert_eq!(tree.height(), 0);
+        assert_eq!(tree.black_height(), 0);
+        assert_eq!(tree.red_count(), 0);
+        assert_eq!(tree.stats(), TreeStats { depth_histogram: vec![], average_search_depth: 0.0 });
+    }
+
+    #[test]
+    fn test_shape_after_inserts() {
+        let mut tree = RBTree::<i32>::new();
+        for i in 1..=3 {
+            tree.insert(i);
+        }
+
+        let root = tree.root().unwrap();
+        assert_eq!(*root.value(), 2);
+        assert_eq!(root.color(), Color::Black);
+        assert_eq!(root.left().map(|node| *node.value()), Some(1));
+        assert_eq!(root.right().map(|node| node.color()), Some(Color::Red));
+        assert!(root.left().unwrap().left().is_none());
+
+        assert_eq!(tree.height(), 2);
+        assert_eq!(tree.black_height(), 1);
+        assert_eq!(tree.red_count(), 2);
+        let stats = tree.stats();
+        assert_eq!(stats.depth_histogram, vec![1, 2]);
+        assert!((stats.average_search_depth - 5.0 / 3.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn test_height_is_logarithmic() {
+        let mut tree = RBTree::<i32>::new();
+        for i in 0..1023 {
+            tree.insert(i);
+        }
+
+        assert!(tree.height() <= 2 * tree.black_height());
+        assert!(tree.height() <= 20);
+        assert_eq!(tree.stats().depth_histogram.iter().sum::<usize>(), 1023);
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_to_dot() {
+        let mut tree = RBTree::<i32>::new();
+        tree.insert(1);
+        tree.insert(2);
+
+        let expected = "digraph RBTree {
+    node [style=filled, fontcolor=white, fontname=\"Helvetica\"];
+    n0 [label=\"1\", fillcolor=black];
+    n1 [label=\"NIL\", shape=box, fillcolor=black, fontsize=8, width=0.3, height=0.2];
+    n2 [label=\"2\", fillcolor=red];
+    n3 [label=\"NIL\", shape=box, fillcolor=black, fontsize=8, width=0.3, height=0.2];
+    n4 [label=\"NIL\", shape=box, fillcolor=black, fontsize=8, width=0.3, height=0.2];
+    n2 -> n3;
+    n2 -> n4;
+    n0 -> n1;
+    n0 -> n2;
+}
+";
+        assert_eq!(tree.to_dot(), expected);
+
+        let mut written = Vec::new();
+        tree.write_dot(&mut written).unwrap();
+        assert_eq!(String::from_utf8(written).unwrap(), expected);
+    }
+
+    #[test]
+    #[cfg(feature = "std")]
+    fn test_to_dot_with_path() {
+        let mut tree = RBTree::<&str>::new();
+        for value in ["b", "a", "c\"", "d"] {
+            tree.insert(value);
+        }
+
+        let dot = tree.to_dot_with_path(&"e");
+        assert!(dot.contains("[label=\"\\\"c\\\\\\\"\\\"\", fillcolor=black, color=gold, penwidth=3];"));
+        assert!(dot.contains("[label=\"\\\"d\\\"\", fillcolor=red, color=gold, penwidth=3];"));
+        assert!(!dot.contains("[label=\"\\\"a\\\"\", fillcolor=black, color=gold"));
+        assert_eq!(dot.matches("color=gold").count(), 7);
+
+        let mut written = Vec::new();
+        tree.write_dot_with_path(&mut written, &"e").unwrap();
+        assert_eq!(String::from_utf8(written).unwrap(), dot);
+    }
+
+    #[test]
+    fn test_display() {
+        let mut tree = RBTree::<i32>::new();
+        assert_eq!(tree.to_string(), "Empty tree\n");
+        for i in 1..=6 {
+            tree.insert(i);
+        }
+
+        assert_eq!(tree.to_string(), "2\n├── 1\n└── \x1b[31m4\x1b[0m\n    ├── 3\n    └── 5\n        ├── ·\n        └── \x1b[31m6\x1b[0m\n");
+        assert_eq!(format!("{:#}", tree), "2\n|-- 1\n`-- 4 (R)\n    |-- 3\n    `-- 5\n        |-- .\n        `-- 6 (R)\n");
+    }
+
+    #[test]
+    fn test_display_options() {
+        let mut tree = RBTree::<i32>::new();
+        for i in 1..=6 {
+            tree.insert(i);
+        }
+
+        assert_eq!(tree.pretty().colors(false).max_depth(1).to_string(), "2\n├── 1\n└── 4 (R)\n    └── …\n");
+        assert_eq!(tree.pretty().ascii().max_depth(0).to_string(), "2\n`-- ...\n");
+    }
+
+    #[test]
+    fn test_iter() {
+        let mut tree = RBTree::<i32>::new();
+        for i in [8, -3, 5, 5, 12] {
+            tree.insert(i);
+        }
+
+        assert_eq!(tree.iter().collect::<Vec<&i32>>(), vec![&-3, &5, &5, &8, &12]);
+        assert_eq!((&tree).into_iter().count(), 5);
+        assert_eq!(RBTree::<i32>::new().iter().next(), None);
+    }
+
+    #[test]
+    fn test_from_iterator() {
+        let tree: RBTree<i32> = vec![3, 1, 3, 2, 1].into_iter().collect();
+        assert!(tree.validate().is_ok());
+        assert_eq!(tree.iter().copied().collect::<Vec<i32>>(), vec![1, 1, 2, 3, 3]);
+
+        let tree: RBTree<i32> = (0..1000).rev().collect();
+        assert!(tree.validate().is_ok());
+        assert_eq!(tree.iter().count(), 1000);
+    }
+
+    #[test]
+    fn test_select_and_rank() {
+        let mut tree: RBTree<i32> = vec![5, 1, 3, 3, 9, 3].into_iter().collect();
+        tree.insert(7);
+        assert!(tree.delete(1));
+
+        assert_eq!(tree.len(), 6);
+        let selected: Vec<i32> = (0..7).filter_map(|index| tree.select(index).copied()).collect();
+        assert_eq!(selected, vec![3, 3, 3, 5, 7, 9]);
+        assert_eq!(tree.rank(&3), 0);
+        assert_eq!(tree.rank(&4), 3);
+        assert_eq!(tree.rank(&9), 5);
+        assert_eq!(tree.rank(&10), 6);
+    }
+
+    #[test]
+    fn test_nearest() {
+        let calibration: RBTree<i32> = vec![10, 20, 20, 35, 50].into_iter().collect();
+        let distance = |a: &i32, b: &i32| (a - b).abs();
+
+        assert_eq!(calibration.nearest(&0, distance), Some(&10));
+        assert_eq!(calibration.nearest(&26, distance), Some(&20));
+        assert_eq!(calibration.nearest(&29, distance), Some(&35));
+        // ties go to the smaller value
+        assert_eq!(calibration.nearest(&15, distance), Some(&10));
+        assert_eq!(calibration.nearest(&99, distance), Some(&50));
+        assert_eq!(RBTree::<i32>::new().nearest(&1, distance), None);
+
+        assert_eq!(calibration.k_nearest(&21, 3, distance), vec![&20, &20, &10]);
+        assert_eq!(calibration.k_nearest(&35, 2, distance), vec![&35, &20]);
+        assert_eq!(calibration.k_nearest(&45, 10, distance), vec![&50, &35, &20, &20, &10]);
+        assert!(calibration.k_nearest(&45, 0, distance).is_empty());
+    }
+
+    #[derive(Clone, Default)]
+    struct CountingAlloc {
+        live: std::rc::Rc<core::cell::Cell<usize>>,
+    }
+
+    unsafe impl Allocator for CountingAlloc {
+        fn allocate(&self, layout: core::alloc::Layout) -> Result<core::ptr::NonNull<[u8]>, allocator_api2::alloc::AllocError> {
+            self.live.set(self.live.get() + 1);
+            Global.allocate(layout)
+        }
+
+        unsafe fn deallocate(&self, ptr: core::ptr::NonNull<u8>, layout: core::alloc::Layout) {
+            self.live.set(self.live.get() - 1);
+            Global.deallocate(ptr, layout)
+        }
+    }
+
+    #[test]
+    fn test_new_in() {
+        let alloc = CountingAlloc::default();
+        let mut tree = RBTree::new_in(alloc.clone());
+        for i in 0..100 {
+            tree.insert(i);
+        }
+        assert_eq!(alloc.live.get(), 100);
+        assert!(tree.validate().is_ok());
+
+        for i in 0..50 {
+            assert!(tree.delete(i * 2));
+        }
+        assert_eq!(alloc.live.get(), 50);
+
+        tree.retain(|value| value % 3 == 0);
+        assert_eq!(alloc.live.get(), 17);
+        assert_eq!(tree.iter().count(), 17);
+
+        drop(tree);
+        assert_eq!(alloc.live.get(), 0);
+    }
+
+    #[test]
+    fn test_take() {
+        let mut tree = RBTree::<(i32, &str)>::new();
+        tree.insert((1, "a"));
+        tree.insert((2, "b"));
+
+        assert_eq!(tree.take(&(2, "b")), Some((2, "b")));
+        assert_eq!(tree.take(&(2, "b")), None);
+        assert_eq!(tree.iter().collect::<Vec<_>>(), vec![&(1, "a")]);
+    }
+
+    #[test]
+    fn test_top_down() {
+        let mut tree = RBTree::new();
+        tree.set_mode(UpdateMode::TopDown);
+        let values: Vec<i32> = (0..200).map(|i| (i * 37) % 50).collect();
+        for value in values.iter() {
+            tree.insert(*value);
+            tools::assert_no_red_violations(&tree);
+            tools::assert_no_black_violations(&tree);
+        }
+        tools::assert_tree_size(&tree, 200);
+
+        // switching modes in between keeps the tree valid
+        for (i, value) in values.iter().enumerate() {
+            tree.set_mode(if i % 3 == 0 { UpdateMode::BottomUp } else { UpdateMode::TopDown });
+            assert!(tree.delete(*value));
+            assert!(!tree.delete(100));
+            assert_eq!(tree.validate(), Ok(()));
+        }
+        assert!(tree.is_empty());
+    }
+
+    #[test]
+    fn test_top_down_take() {
+        let mut tree = RBTree::<(i32, &str)>::new();
+        tree.set_mode(UpdateMode::TopDown);
+        for (i, name) in ["a", "b", "c", "d", "e"].iter().enumerate() {
+            tree.insert((i as i32, *name));
+        }
+
+        assert_eq!(tree.take(&(1, "b")), Some((1, "b")));
+        assert_eq!(tree.take(&(1, "b")), None);
+        assert_eq!(tree.iter().map(|(i, _)| *i).collect::<Vec<i32>>(), vec![0, 2, 3, 4]);
+    }
+
+    #[test]
+    fn test_transaction_commit() {
+        let mut tree = RBTree::<i32>::new();
+        for i in 0..10 {
+            tree.insert(i);
+        }
+
+        let result: Result<usize, ()> = tree.transaction(|tx| {
+            tx.insert(20);
+            assert!(tx.delete(3));
+            assert!(tx.contains(20));
+            Ok(tx.iter().count())
+        });
+
+        assert_eq!(result, Ok(10));
+        assert_eq!(tree.iter().copied().collect::<Vec<i32>>(), vec![0, 1, 2, 4, 5, 6, 7, 8, 9, 20]);
+        assert!(tree.validate().is_ok());
+    }
+
+    #[test]
+    fn test_transaction_rollback() {
+        let mut tree = RBTree::<i32>::new();
+        for i in 0..50 {
+            tree.insert(i % 10);
+        }
+        let before: Vec<i32> = tree.iter().copied().collect();
+
+        let result: Result<(), &str> = tree.transaction(|tx| {
+            for i in 0..100 {
+                tx.insert(i);
+            }
+            for i in 0..20 {
+                tx.delete(i % 7);
+            }
+            Err("rejected")
+        });
+
+        assert_eq!(result, Err("rejected"));
+        assert_eq!(tree.iter().copied().collect::<Vec<i32>>(), before);
+        assert!(tree.validate().is_ok());
+    }
+
+    #[test]
+    fn test_transaction_rollback_keeps_shape() {
+        use tools::{shape, Tagged};
+
+        let mut tree = RBTree::new();
+        for i in 0..200 {
+            tree.insert(Tagged((i * 7) % 30, i as usize));
+        }
+        tree.set_mode(UpdateMode::TopDown);
+        let label = |value: &Tagged| (value.0, value.1);
+        let before = shape(&tree, label);
+
+        let result: Result<(), ()> = tree.transaction(|tx| {
+            for i in 0..60 {
+                tx.insert(Tagged((i * 11) % 40, 1000 + i as usize));
+                assert!(tx.delete(Tagged((i * 3) % 30, 0)));
+            }
+            for i in 0..30 {
+                tx.delete(Tagged(i, 0));
+            }
+            Err(())
+        });
+
+        assert_eq!(result, Err(()));
+        assert_eq!(shape(&tree, label), before);
+        assert!(tree.validate().is_ok());
+    }
+
+    #[test]
+    fn test_transaction_rollback_on_panic() {
+        let mut tree = RBTree::<i32>::new();
+        for i in 0..20 {
+            tree.insert(i);
+        }
+        let before: Vec<i32> = tree.iter().copied().collect();
+
+        let outcome = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let _: Result<(), ()> = tree.transaction(|tx| {
+                tx.delete(5);
+                tx.insert(100);
+                panic!("failed halfway");
+            });
+        }));
+
+        assert!(outcome.is_err());
+        assert_eq!(tree.iter().copied().collect::<Vec<i32>>(), before);
+        assert!(tree.validate().is_ok());
+    }
+
+    #[test]
+    fn test_send_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<RBTree<i32>>();
+        assert_send_sync::<RBTreeMap<i32, i32>>();
+        assert_send_sync::<LLRBTree<i32>>();
+        assert_send_sync::<crate::balanced::BalancedTree<i32>>();
+        assert_send_sync::<crate::persistent::PersistentRBTree<i32>>();
+        assert_send_sync::<crate::rolling::RollingQuantile<i32>>();
+        #[cfg(feature = "std")]
+        assert_send_sync::<crate::concurrent::ConcurrentRBTree<i32>>();
+    }
+
+    fn record_events(tree: &mut RBTree<i32>) -> std::sync::Arc<std::sync::Mutex<Vec<String>>> {
+        let events = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink = events.clone();
+        tree.observe(move |event| sink.lock().unwrap().push(format!("{:?}", event)));
+        events
+    }
+
+    #[test]
+    fn test_observe_rebalancing() {
+        let mut tree = RBTree::<i32>::new();
+        let events = record_events(&mut tree);
+        for i in 1..=3 {
+            tree.insert(i);
+        }
+
+        assert_eq!(*events.lock().unwrap(), vec![
+            "Insert(1)",
+            "Insert(2)",
+            "Insert(3)",
+            "Rotate { value: 1, direction: Left }",
+            "Recolor { value: 2, color: Black }",
+            "Recolor { value: 1, color: Red }",
+        ]);
+
+        tree.insert(4);
+        events.lock().unwrap().clear();
+        tree.delete(1);
+        assert_eq!(*events.lock().unwrap(), vec![
+            "Delete(1)",
+            "Rotate { value: 2, direction: Left }",
+            "Recolor { value: 4, color: Black }",
+        ]);
+    }
+
+    #[test]
+    fn test_observe_mirror() {
+        let mut tree = RBTree::<i32>::new();
+        let mirror = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+        let sink = mirror.clone();
+        tree.observe(move |event| {
+            let mut values = sink.lock().unwrap();
+            match event {
+                Event::Insert(value) => values.push(**value),
+                Event::Delete(value) => {
+                    let position = values.iter().position(|v| v == *value).unwrap();
+                    values.remove(position);
+                },
+                _ => {},
+            }
+        });
+
+        for i in 0..200 {
+            tree.insert((i * 37) % 101);
+        }
+        for i in 0..60 {
+            tree.delete((i * 13) % 101);
+        }
+        tree.retain(|value| value % 4 != 0);
+        tree.extract_if(|value| value % 5 == 0).count();
+
+        let mut values = mirror.lock().unwrap().clone();
+        values.sort();
+        assert_eq!(values, tree.iter().copied().collect::<Vec<i32>>());
+
+        tree.drain().count();
+        assert!(mirror.lock().unwrap().is_empty());
+
+        tree.clear_observers();
+        tree.insert(1);
+        assert!(mirror.lock().unwrap().is_empty());
+    }
+
+    #[test]
+    fn test_trace() {
+        use TraceEvent::*;
+
+        let mut tree = RBTree::<i32>::new();
+        tree.insert(0);
+        assert!(tree.trace().is_empty());
+
+        tree.start_trace();
+        tree.delete(0);
+        for i in 1..=3 {
+            tree.insert(i);
+        }
+        assert_eq!(tree.stop_trace(), vec![
+            Visit(0),
+            Delete(0),
+            Insert(1),
+            Visit(1),
+            Insert(2),
+            Case(FixupCase::InsertBlackParent),
+            Visit(1),
+            Visit(2),
+            Insert(3),
+            Case(FixupCase::InsertOuterGrandchild),
+            Rotate { value: 1, kind: RotationKind::Single, direction: Direction::Left },
+            Recolor { value: 2, color: Color::Black },
+            Recolor { value: 1, color: Color::Red },
+        ]);
+
+        tree.insert(4);
+        assert!(tree.trace().is_empty());
+
+        tree.start_trace();
+        tree.delete(1);
+        assert_eq!(tree.trace(), [
+            Visit(2),
+            Visit(1),
+            Delete(1),
+            Case(FixupCase::DeleteDistantNephew),
+            Rotate { value: 2, kind: RotationKind::Single, direction: Direction::Left },
+            Recolor { value: 4, color: Color::Black },
+        ]);
+    }
+
+    #[test]
+    fn test_trace_delete_cases() {
+        let mut tree = RBTree::<i32>::new();
+        for i in 0..64 {
+            tree.insert(i);
+        }
+        tree.start_trace();
+        for i in 0..64 {
+            tree.delete((i * 29) % 64);
+        }
+
+        let cases: Vec<FixupCase> = tree.trace().iter().filter_map(|event| match event {
+            TraceEvent::Case(case) => Some(*case),
+            _ => None,
+        }).collect();
+        for case in [FixupCase::DeleteAllBlack, FixupCase::DeleteRedSibling, FixupCase::DeleteRedParent, FixupCase::DeleteDistantNephew] {
+            assert!(cases.contains(&case), "{:?} never happened", case);
+        }
+        assert_eq!(tree.trace().iter().filter(|event| matches!(event, TraceEvent::Delete(_))).count(), 64);
+    }
+
+    #[cfg(feature = "metrics")]
+    #[test]
+    fn test_metrics() {
+        let mut tree = RBTree::<i32>::new();
+        for i in 1..=3 {
+            tree.insert(i);
+        }
+        assert_eq!(tree.metrics(), Metrics {
+            operations: 3,
+            comparisons: 3,
+            single_rotations: 1,
+            double_rotations: 0,
+            recolors: 2,
+            total_depth: 3,
+            max_depth: 2,
+        });
+
+        tree.reset_metrics();
+        assert!(tree.contains(3));
+        assert!(!tree.delete(5));
+        let metrics = tree.metrics();
+        assert_eq!((metrics.operations, metrics.comparisons, metrics.max_depth), (2, 7, 2));
+        assert_eq!(metrics.average_depth(), 2.0);
+
+        let mut tree = RBTree::<i32>::new();
+        for i in [1, 3, 2] {
+            tree.insert(i);
+        }
+        assert_eq!((tree.metrics().single_rotations, tree.metrics().double_rotations), (0, 1));
+    }
+
+    #[cfg(feature = "serde")]
+    fn deserialize_from(values: Vec<i32>) -> Result<RBTree<i32>, ::serde::de::value::Error> {
+        use ::serde::de::value::SeqDeserializer;
+        use ::serde::Deserialize;
+
+        RBTree::deserialize(SeqDeserializer::new(values.into_iter()))
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_serialize() {
+        use serde_test::{assert_ser_tokens, Token};
+
+        let mut tree = RBTree::<i32>::new();
+        for i in [3, 1, 2, 2] {
+            tree.insert(i);
+        }
+
+        assert_ser_tokens(&tree, &[
+            Token::Seq { len: None },
+            Token::I32(1),
+            Token::I32(2),
+            Token::I32(2),
+            Token::I32(3),
+            Token::SeqEnd,
+        ]);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_deserialize_builds_valid_tree() {
+        let tree = deserialize_from((0..100).chain([100, 100, 101, 101]).collect()).unwrap();
+
+        assert_eq!(tree.validate(), Ok(()));
+        tools::assert_tree_size(&tree, 104);
+        assert_eq!(tree.iter().filter(|value| **value == 101).count(), 2);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_deserialize_rejects_unsorted() {
+        let error = deserialize_from(vec![1, 5, 4]).err().unwrap();
+        assert_eq!(error.to_string(), "sequence is not sorted, element 2 is smaller than the one before it");
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_map_tokens() {
+        use ::serde::{Deserialize, Serialize};
+        use serde_test::{assert_tokens, Token};
+
+        // compared by the entries, which the map itself can't be
+        #[derive(Serialize, Deserialize)]
+        #[serde(transparent)]
+        struct Map(RBTreeMap<i32, char>);
+
+        impl PartialEq for Map {
+            fn eq(&self, other: &Map) -> bool {
+                self.0.iter().eq(other.0.iter())
+            }
+        }
+
+        impl fmt::Debug for Map {
+            fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+                self.0.fmt(formatter)
+            }
+        }
+
+        let map = Map([(2, 'b'), (3, 'c'), (1, 'a')].iter().copied().collect());
+
+        assert_tokens(&map, &[
+            Token::Map { len: None },
+            Token::I32(1),
+            Token::Char('a'),
+            Token::I32(2),
+            Token::Char('b'),
+            Token::I32(3),
+            Token::Char('c'),
+            Token::MapEnd,
+        ]);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_deserialize_map() {
+        use ::serde::de::value::{Error, MapDeserializer};
+        use ::serde::Deserialize;
+
+        let deserialize = |entries: Vec<(i32, i32)>| RBTreeMap::<i32, i32>::deserialize(MapDeserializer::<_, Error>::new(entries.into_iter()));
+
+        // keys may come in any order
+        let map = deserialize((0..100).rev().map(|key| (key, -key)).collect()).unwrap();
+        assert_eq!(map.len(), 100);
+        assert_eq!(map.get(&40), Some(&-40));
+        assert!(map.tree.validate().is_ok());
+
+        let error = deserialize(vec![(1, 1), (3, 3), (2, 2), (3, 4)]).err().unwrap();
+        assert_eq!(error.to_string(), "duplicate key in map, 2 keys are smaller than it");
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn test_trace_event_tokens() {
+        use serde_test::{assert_tokens, Token};
+
+        let event = TraceEvent::Rotate { value: 7, kind: RotationKind::Double, direction: Direction::Right };
+        assert_tokens(&event, &[
+            Token::StructVariant { name: "TraceEvent", variant: "Rotate", len: 3 },
+            Token::Str("value"),
+            Token::I32(7),
+            Token::Str("kind"),
+            Token::UnitVariant { name: "RotationKind", variant: "Double" },
+            Token::Str("direction"),
+            Token::UnitVariant { name: "Direction", variant: "Right" },
+            Token::StructVariantEnd,
+        ]);
+        assert_tokens(&TraceEvent::<i32>::Case(FixupCase::DeleteRedSibling), &[
+            Token::NewtypeVariant { name: "TraceEvent", variant: "Case" },
+            Token::UnitVariant { name: "FixupCase", variant: "DeleteRedSibling" },
+        ]);
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn test_par_iter() {
+        use ::rayon::prelude::*;
+
+        let mut tree = RBTree::<i64>::new();
+        for i in (0..10_000).rev() {
+            tree.insert(i % 5_000);
+        }
+
+        assert_eq!(tree.par_iter().sum::<i64>(), 2 * (0..5_000).sum::<i64>());
+        let evens: Vec<&i64> = tree.par_iter().filter(|value| *value % 2 == 0).collect();
+        assert_eq!(evens, tree.iter().filter(|value| *value % 2 == 0).collect::<Vec<&i64>>());
+        assert_eq!(RBTree::<i64>::new().par_iter().count(), 0);
+
+        let owned: Vec<i64> = tree.into_par_iter().map(|value| value * 2).collect();
+        assert_eq!(owned.len(), 10_000);
+        assert!(owned.windows(2).all(|pair| pair[0] <= pair[1]));
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn test_par_extend_and_collect() {
+        use ::rayon::prelude::*;
+
+        let mut tree: RBTree<i32> = (0..1_000).into_par_iter().filter(|value| value % 2 == 0).collect();
+        tree.par_extend((0..1_000).into_par_iter().rev());
+
+        assert_eq!(tree.validate(), Ok(()));
+        tools::assert_tree_size(&tree, 1_500);
+        assert!(tree.iter().zip(tree.iter().skip(1)).all(|(a, b)| a <= b));
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn test_par_extend_notifies() {
+        use ::rayon::prelude::*;
+
+        let mut tree: RBTree<i32> = (0..1_000).collect();
+        let events = record_events(&mut tree);
+        // small next to the tree, so inserted one at a time
+        tree.par_extend([5, 1_500, 7].par_iter().copied());
+        let inserted: Vec<String> = events.lock().unwrap().iter().filter(|event| event.starts_with("Insert")).cloned().collect();
+        assert_eq!(inserted, vec!["Insert(5)", "Insert(1500)", "Insert(7)"]);
+        assert_eq!(tree.validate(), Ok(()));
+
+        // large enough to rebuild, the events come in order once the values are in
+        events.lock().unwrap().clear();
+        tree.par_extend((0..500).into_par_iter().map(|value| value * 3));
+        let expected: Vec<String> = (0..500).map(|value| format!("Insert({})", value * 3)).collect();
+        assert_eq!(*events.lock().unwrap(), expected);
+        assert_eq!(tree.validate(), Ok(()));
+        tools::assert_tree_size(&tree, 1_503);
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    fn test_par_from_sorted() {
+        let values: Vec<u32> = (0..100_000).map(|value| value / 3).collect();
+        let tree = RBTree::par_from_sorted(&values);
+
+        assert_eq!(tree.validate(), Ok(()));
+        assert_eq!(tree.iter().copied().collect::<Vec<u32>>(), values);
+        assert_eq!(RBTree::<u32>::par_from_sorted(&[]).validate(), Ok(()));
+    }
+
+    #[cfg(feature = "rayon")]
+    #[test]
+    #[should_panic(expected = "par_from_sorted needs sorted values")]
+    fn test_par_from_sorted_rejects_unsorted() {
+        RBTree::par_from_sorted(&[1, 3, 2]);
+    }
 }
@@ -0,0 +1,107 @@
+use super::*;
+
+// Walks away from a query in one direction: going right it visits the values that are not less
+// than the query in ascending order, going left the smaller ones in descending order.
+struct Outward<'a, N: SearchNode> {
+    stack: Vec<&'a N>,
+    dir: Direction,
+}
+
+impl<'a, N: SearchNode> Outward<'a, N> {
+    fn new(root: Option<&'a N>, query: &N::Value, dir: Direction) -> Outward<'a, N> {
+        let mut outward = Outward { stack: Vec::new(), dir };
+        let mut next = root;
+        while let Some(node) = next {
+            let ahead = match dir {
+                Direction::Right => node.value() >= query,
+                Direction::Left => node.value() < query,
+            };
+            if ahead {
+                outward.stack.push(node);
+                next = node.child(dir.opposite());
+            } else {
+                next = node.child(dir);
+            }
+        }
+        outward
+    }
+}
+
+impl<'a, N: SearchNode> Iterator for Outward<'a, N> {
+    type Item = &'a N::Value;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let node = self.stack.pop()?;
+        let mut next = node.child(self.dir);
+        while let Some(child) = next {
+            self.stack.push(child);
+            next = child.child(self.dir.opposite());
+        }
+        Some(node.value())
+    }
+}
+
+// `distance` has to grow the further a value is from the query in sorted order, on both sides,
+// which holds for the usual `|a - b|`. Equally distant values are returned smaller one first.
+pub fn nearest<'a, N: SearchNode, D: PartialOrd, F: Fn(&N::Value, &N::Value) -> D>(root: Option<&'a N>, query: &N::Value, distance: F) -> Option<&'a N::Value> {
+    // the largest value less than the query and the smallest one that is not
+    let mut below = None;
+    let mut above = None;
+    let mut next = root;
+    while let Some(node) = next {
+        if node.value() < query {
+            below = Some(node.value());
+            next = node.child(Direction::Right);
+        } else {
+            above = Some(node.value());
+            next = node.child(Direction::Left);
+        }
+    }
+    match (below, above) {
+        (Some(below), Some(above)) => {
+            if distance(above, query) < distance(below, query) {
+                Some(above)
+            } else {
+                Some(below)
+            }
+        },
+        (below, above) => below.or(above),
+    }
+}
+
+pub fn k_nearest<'a, N: SizedNode, D: PartialOrd, F: Fn(&N::Value, &N::Value) -> D>(root: Option<&'a N>, query: &N::Value, k: usize, distance: F) -> Vec<&'a N::Value> {
+    let mut below = Outward::new(root, query, Direction::Left).peekable();
+    let mut above = Outward::new(root, query, Direction::Right).peekable();
+    let mut nearest = Vec::with_capacity(k.min(size(root)));
+    while nearest.len() < k {
+        let closer = match (below.peek(), above.peek()) {
+            (Some(low), Some(high)) => {
+                if distance(high, query) < distance(low, query) {
+                    above.next()
+                } else {
+                    below.next()
+                }
+            },
+            (Some(_), None) => below.next(),
+            (None, _) => above.next(),
+        };
+        match closer {
+            Some(value) => nearest.push(value),
+            None => break,
+        }
+    }
+    nearest
+}
+
+impl<T: Ord, A: Allocator> RBTree<T, A> {
+    /// The value closest to `query` by `distance`, in O(log n).
+    pub fn nearest<D: PartialOrd, F: Fn(&T, &T) -> D>(&self, query: &T, distance: F) -> Option<&T> {
+        nearest(self.root.as_deref(), query, distance)
+    }
+
+    /// The `k` values closest to `query` by `distance`, nearest first, in O(log n + k).
+    /// Returns fewer if the tree has fewer values.
+    pub fn k_nearest<D: PartialOrd, F: Fn(&T, &T) -> D>(&self, query: &T, k: usize, distance: F) -> Vec<&T> {
+        k_nearest(self.root.as_deref(), query, k, distance)
+    }
+}
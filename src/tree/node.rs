@@ -1,48 +1,54 @@
 
 use super::*;
 
-pub struct Node<T: Ord> {
-    pub left: Option<Box<Node<T>>>,
-    pub right: Option<Box<Node<T>>>,
+pub struct Node<T: Ord, A: Allocator = Global> {
+    pub left: Option<Box<Node<T, A>, A>>,
+    pub right: Option<Box<Node<T, A>, A>>,
     pub value: T,
 
     pub color: Color,
+    // Number of nodes in the subtree rooted here, for the order statistics.
+    pub size: usize,
 }
 
-impl<T: Ord> Node<T> {
-    pub fn new(color: Color, value: T) -> Node<T> {
-        Node { color: color, value: value, left: None, right: None }
+impl<T: Ord, A: Allocator> Node<T, A> {
+    pub fn new(color: Color, value: T) -> Node<T, A> {
+        Node { color, value, left: None, right: None, size: 1 }
     }
 
-    pub fn get_child(&mut self, dir: Direction) -> Option<&mut Box<Node<T>>> {
+    pub fn get_child(&mut self, dir: Direction) -> Option<&mut Box<Node<T, A>, A>> {
         match dir {
             Direction::Left => self.left.as_mut(),
             Direction::Right => self.right.as_mut(),
         }
     }
 
-    pub fn get_child_as_ref(&mut self, dir: Direction) -> Option<&Box<Node<T>>> {
+    pub fn get_child_as_ref(&mut self, dir: Direction) -> Option<&Node<T, A>> {
         match dir {
-            Direction::Left => self.left.as_ref(),
-            Direction::Right => self.right.as_ref(),
+            Direction::Left => self.left.as_deref(),
+            Direction::Right => self.right.as_deref(),
         }
     }
 
-    pub fn set_child(&mut self, dir: Direction, node: Node<T>) {
+    pub fn link(&mut self, dir: Direction) -> &mut Option<Box<Node<T, A>, A>> {
         match dir {
-            Direction::Left => self.left = Some(Box::new(node)),
-            Direction::Right => self.right = Some(Box::new(node)),
+            Direction::Left => &mut self.left,
+            Direction::Right => &mut self.right,
         }
     }
 
-    pub fn set_child_or_leaf(&mut self, dir: Direction, child: Option<Box<Node<T>>>) {
+    pub fn set_child(&mut self, dir: Direction, node: Box<Node<T, A>, A>) {
+        self.set_child_or_leaf(dir, Some(node));
+    }
+
+    pub fn set_child_or_leaf(&mut self, dir: Direction, child: Option<Box<Node<T, A>, A>>) {
         match dir {
             Direction::Left => self.left = child,
             Direction::Right => self.right = child,
         }
     }
 
-    pub fn remove_child(&mut self, dir: Direction) -> Option<Box<Node<T>>> {
+    pub fn remove_child(&mut self, dir: Direction) -> Option<Box<Node<T, A>, A>> {
         match dir {
             Direction::Left => {
                 self.left.take()
@@ -57,32 +63,42 @@ impl<T: Ord> Node<T> {
         self.color == Color::Black
     }
 
-    pub fn rotate(self, rtype: RotationType) -> Node<T> {
+    // Recomputes `size` from the children, whose sizes have to be right already.
+    pub fn update_size(&mut self) {
+        self.size = 1 + subtree_size(self.left.as_deref()) + subtree_size(self.right.as_deref());
+    }
+
+    // Rotations move the boxes around, so no node is reallocated. The new top takes over the
+    // size of the old one, so a size that was not adjusted yet for a pending insert or delete
+    // stays the same kind of wrong, the nodes moved down are recomputed from their children.
+    pub fn rotate(node: Box<Node<T, A>, A>, rtype: RotationType) -> Box<Node<T, A>, A> {
         match rtype {
-            RotationType::Single(dir) => self.rotate_once(dir),
-            RotationType::Double(dir) => self.rotate_twice(dir),
+            RotationType::Single(dir) => Self::rotate_once(node, dir),
+            RotationType::Double(dir) => Self::rotate_twice(node, dir),
         }
     }
 
-    fn rotate_once(mut self, dir: Direction) -> Node<T> {
-        let u = self.remove_child(dir);
-        let mut p = *(self.remove_child(dir.opposite()).expect("Rotation need one child"));
+    fn rotate_once(mut node: Box<Node<T, A>, A>, dir: Direction) -> Box<Node<T, A>, A> {
+        let u = node.remove_child(dir);
+        let mut p = node.remove_child(dir.opposite()).expect("Rotation need one child");
         let n = p.remove_child(dir.opposite());
         let s = p.remove_child(dir);
 
-        self.set_child_or_leaf(dir, u);
-        self.set_child_or_leaf(dir.opposite(), s);
-        
-        p.set_child(dir, self);
+        node.set_child_or_leaf(dir, u);
+        node.set_child_or_leaf(dir.opposite(), s);
+
+        p.size = node.size;
+        node.update_size();
+        p.set_child(dir, node);
         p.set_child_or_leaf(dir.opposite(), n);
 
         p
     }
 
-    fn rotate_twice(mut self, dir: Direction) -> Node<T> {
-        let u = self.remove_child(dir);
-        let mut p = *(self.remove_child(dir.opposite()).expect("Double rotation needs the parent"));
-        let mut n = *(p.remove_child(dir).expect("Double rotation needs inner grandchild"));
+    fn rotate_twice(mut node: Box<Node<T, A>, A>, dir: Direction) -> Box<Node<T, A>, A> {
+        let u = node.remove_child(dir);
+        let mut p = node.remove_child(dir.opposite()).expect("Double rotation needs the parent");
+        let mut n = p.remove_child(dir).expect("Double rotation needs inner grandchild");
         let s = p.remove_child(dir.opposite());
 
         let b1 = n.remove_child(dir.opposite());
@@ -90,13 +106,16 @@ impl<T: Ord> Node<T> {
 
         p.set_child_or_leaf(dir.opposite(), s);
         p.set_child_or_leaf(dir, b1);
-        
-        self.set_child_or_leaf(dir.opposite(), b2);
-        self.set_child_or_leaf(dir, u);
 
+        node.set_child_or_leaf(dir.opposite(), b2);
+        node.set_child_or_leaf(dir, u);
+
+        n.size = node.size;
+        p.update_size();
+        node.update_size();
         n.set_child(dir.opposite(), p);
-        n.set_child(dir, self);
+        n.set_child(dir, node);
 
         n
     }
-}
\ No newline at end of file
+}
@@ -0,0 +1,110 @@
+use alloc::boxed::Box as StdBox;
+
+use super::*;
+use super::metrics::Counters;
+use super::trace::Recorder;
+
+/// A change to an `RBTree`, passed to the callbacks registered with `RBTree::observe`.
+///
+/// `Insert` and `Delete` fire once per value that enters or leaves the tree. The rebalancing
+/// that follows shows up as `Rotate` and `Recolor` events, in the order the algorithm performs them.
+#[derive(Debug, PartialEq, Eq)]
+pub enum Event<'a, T> {
+    Insert(&'a T),
+    Delete(&'a T),
+    /// The node holding `value` moved down to the `direction` side of its former child.
+    Rotate { value: &'a T, direction: Direction },
+    Recolor { value: &'a T, color: Color },
+}
+
+type Callback<T> = StdBox<dyn FnMut(&Event<'_, T>) + Send + Sync>;
+
+// The registered callbacks, the trace if one is being recorded, and the metrics counters.
+pub(crate) struct Observers<T> {
+    callbacks: Vec<Callback<T>>,
+    pub trace: Option<Recorder<T>>,
+    pub counters: Counters,
+}
+
+impl<T> Observers<T> {
+    pub fn new() -> Observers<T> {
+        Observers { callbacks: Vec::new(), trace: None, counters: Counters::new() }
+    }
+
+    pub fn notify(&mut self, event: Event<'_, T>) {
+        match event {
+            Event::Insert(value) => self.record(|clone| TraceEvent::Insert(clone(value))),
+            Event::Delete(value) => self.record(|clone| TraceEvent::Delete(clone(value))),
+            Event::Recolor { value, color } => self.record(|clone| TraceEvent::Recolor { value: clone(value), color }),
+            // traced by `rotate`, which still knows the kind of rotation
+            Event::Rotate { .. } => {},
+        }
+        for callback in self.callbacks.iter_mut() {
+            callback(&event);
+        }
+    }
+
+    // Only builds the event when a trace is being recorded.
+    pub fn record<F: FnOnce(fn(&T) -> T) -> TraceEvent<T>>(&mut self, make_event: F) {
+        if let Some(recorder) = self.trace.as_mut() {
+            let event = make_event(recorder.clone);
+            recorder.events.push(event);
+        }
+    }
+
+    pub fn visit(&mut self, value: &T) {
+        self.counters.visited();
+        self.record(|clone| TraceEvent::Visit(clone(value)));
+    }
+
+    pub fn fixup(&mut self, case: FixupCase) {
+        self.record(|_| TraceEvent::Case(case));
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.callbacks.is_empty() && self.trace.is_none()
+    }
+}
+
+impl<T: Ord, A: Allocator> RBTree<T, A> {
+    /// Registers a callback that is called for every change made to the tree from now on.
+    ///
+    /// Callbacks have to be `Send` and `Sync` so the tree stays both. State they share with the
+    /// rest of the program goes through an `Arc<Mutex<_>>`.
+    pub fn observe<F: FnMut(&Event<'_, T>) + Send + Sync + 'static>(&mut self, callback: F) {
+        self.observers.callbacks.push(StdBox::new(callback));
+    }
+
+    /// Removes every callback registered with `observe`.
+    pub fn clear_observers(&mut self) {
+        self.observers.callbacks.clear();
+    }
+}
+
+// Recolors `node`, notifying only when the color actually changes.
+pub(crate) fn set_color<T: Ord, A: Allocator>(node: &mut Node<T, A>, color: Color, observers: &mut Observers<T>) {
+    if node.color != color {
+        node.color = color;
+        observers.counters.recolored();
+        observers.notify(Event::Recolor { value: &node.value, color });
+    }
+}
+
+// `Node::rotate` with a `Rotate` event for every single rotation it is made of.
+pub(crate) fn rotate<T: Ord, A: Allocator>(node: Box<Node<T, A>, A>, rtype: RotationType, observers: &mut Observers<T>) -> Box<Node<T, A>, A> {
+    let kind = match rtype {
+        RotationType::Single(_) => RotationKind::Single,
+        RotationType::Double(_) => RotationKind::Double,
+    };
+    observers.counters.rotated(kind);
+    if !observers.is_empty() {
+        observers.record(|clone| TraceEvent::Rotate { value: clone(&node.value), kind, direction: rtype.get_direction() });
+        if let RotationType::Double(dir) = rtype {
+            let child = node.child(dir.opposite()).expect("Double rotation needs the parent");
+            observers.notify(Event::Rotate { value: &child.value, direction: dir.opposite() });
+        }
+        let dir = rtype.get_direction();
+        observers.notify(Event::Rotate { value: &node.value, direction: dir });
+    }
+    Node::rotate(node, rtype)
+}
@@ -0,0 +1,133 @@
+use core::cmp::Ordering;
+use core::ops::{Bound, RangeBounds};
+
+use super::*;
+
+// Read access to a node that stores the number of nodes in its subtree, so the trees with other
+// node types can share the order statistics. The sizes let positions in sorted order be found
+// on the way down.
+pub trait SizedNode: SearchNode {
+    fn size(&self) -> usize;
+}
+
+impl<T: Ord, A: Allocator> SizedNode for Node<T, A> {
+    fn size(&self) -> usize {
+        self.size
+    }
+}
+
+pub fn size<N: SizedNode>(node_or_leaf: Option<&N>) -> usize {
+    node_or_leaf.map_or(0, SizedNode::size)
+}
+
+// The value for which `compare` returns `Equal`. `compare` tells how the value looked for
+// compares to the value of a node.
+pub fn search<N: SearchNode, F: Fn(&N::Value) -> Ordering>(root: Option<&N>, compare: F) -> Option<&N::Value> {
+    let mut next = root;
+    while let Some(node) = next {
+        match compare(node.value()) {
+            Ordering::Less => next = node.child(Direction::Left),
+            Ordering::Equal => return Some(node.value()),
+            Ordering::Greater => next = node.child(Direction::Right),
+        }
+    }
+    None
+}
+
+pub fn select<N: SizedNode>(root: Option<&N>, mut index: usize) -> Option<&N::Value> {
+    let mut next = root;
+    while let Some(node) = next {
+        let left_size = size(node.child(Direction::Left));
+        match index.cmp(&left_size) {
+            Ordering::Less => next = node.child(Direction::Left),
+            Ordering::Equal => return Some(node.value()),
+            Ordering::Greater => {
+                index -= left_size + 1;
+                next = node.child(Direction::Right);
+            },
+        }
+    }
+    None
+}
+
+// Counts the values `below` holds for, which have to come before all the others.
+pub fn count_while<N: SizedNode, F: Fn(&N::Value) -> bool>(root: Option<&N>, below: F) -> usize {
+    let mut count = 0;
+    let mut next = root;
+    while let Some(node) = next {
+        if below(node.value()) {
+            count += size(node.child(Direction::Left)) + 1;
+            next = node.child(Direction::Right);
+        } else {
+            next = node.child(Direction::Left);
+        }
+    }
+    count
+}
+
+// The positions in sorted order of the first value inside `range` and of the first one after it.
+pub fn range_positions<N: SizedNode, R: RangeBounds<N::Value>>(root: Option<&N>, range: &R) -> (usize, usize) {
+    let start = match range.start_bound() {
+        Bound::Included(start) => count_while(root, |value| value < start),
+        Bound::Excluded(start) => count_while(root, |value| value <= start),
+        Bound::Unbounded => 0,
+    };
+    let end = match range.end_bound() {
+        Bound::Included(end) => count_while(root, |value| value <= end),
+        Bound::Excluded(end) => count_while(root, |value| value < end),
+        Bound::Unbounded => size(root),
+    };
+    (start, end.max(start))
+}
+
+// What a delete looks for, compared with the node values on the way down: a value in any
+// borrowed form, a position in sorted order, or a comparison.
+pub trait Target<T> {
+    fn locate(&mut self, value: &T, left_size: usize) -> Ordering;
+}
+
+impl<T: Borrow<Q>, Q: ?Sized + Ord> Target<T> for &Q {
+    fn locate(&mut self, value: &T, _: usize) -> Ordering {
+        (**self).cmp(value.borrow())
+    }
+}
+
+// The index is relative to the subtree the search is in.
+pub struct Position(pub usize);
+
+impl<T> Target<T> for Position {
+    fn locate(&mut self, _: &T, left_size: usize) -> Ordering {
+        let ordering = self.0.cmp(&left_size);
+        if ordering == Ordering::Greater {
+            self.0 -= left_size + 1;
+        }
+        ordering
+    }
+}
+
+// How the value looked for compares to the value of a node.
+pub struct Compare<F>(pub F);
+
+impl<T, F: Fn(&T) -> Ordering> Target<T> for Compare<F> {
+    fn locate(&mut self, value: &T, _: usize) -> Ordering {
+        (self.0)(value)
+    }
+}
+
+impl<T: Ord, A: Allocator> RBTree<T, A> {
+    /// Number of values in the tree, duplicates included.
+    pub fn len(&self) -> usize {
+        subtree_size(self.root.as_deref())
+    }
+
+    /// The value at `index` in sorted order, counting from 0, in O(log n).
+    pub fn select(&self, index: usize) -> Option<&T> {
+        select(self.root.as_deref(), index)
+    }
+
+    /// Number of values less than `value`, which is the index of its first copy in sorted
+    /// order if it is in the tree. O(log n).
+    pub fn rank(&self, value: &T) -> usize {
+        count_while(self.root.as_deref(), |node_value| node_value < value)
+    }
+}
@@ -0,0 +1,230 @@
+use ::rayon::iter::plumbing::{bridge_unindexed, Folder, UnindexedConsumer, UnindexedProducer};
+use ::rayon::prelude::*;
+
+use super::*;
+
+// Below this many values a subtree is built on the current thread.
+const SEQUENTIAL_BUILD_LEN: usize = 4096;
+
+/// Parallel iterator over the values of an `RBTree`, in order.
+pub struct ParIter<'a, T: Ord> {
+    root: Option<&'a Node<T>>,
+}
+
+/// Owning parallel iterator over the values of an `RBTree`, in order.
+pub struct IntoParIter<T: Ord> {
+    root: Option<Box<Node<T>>>,
+}
+
+enum Piece<'a, T: Ord> {
+    Subtree(&'a Node<T>),
+    Value(&'a T),
+}
+
+enum OwnedPiece<T: Ord> {
+    Subtree(Box<Node<T>>),
+    Value(T),
+}
+
+// An in-order run of subtrees and single values. Splitting breaks the first subtree
+// into its left child, its value and its right child.
+struct RefProducer<'a, T: Ord> {
+    pieces: Vec<Piece<'a, T>>,
+}
+
+struct OwnedProducer<T: Ord> {
+    pieces: Vec<OwnedPiece<T>>,
+}
+
+impl<'a, T: Ord + Sync> UnindexedProducer for RefProducer<'a, T> {
+    type Item = &'a T;
+
+    fn split(mut self) -> (Self, Option<Self>) {
+        if let [Piece::Subtree(node)] = self.pieces[..] {
+            self.pieces.clear();
+            if let Some(left) = node.left.as_deref() {
+                self.pieces.push(Piece::Subtree(left));
+            }
+            self.pieces.push(Piece::Value(&node.value));
+            if let Some(right) = node.right.as_deref() {
+                self.pieces.push(Piece::Subtree(right));
+            }
+        }
+        if self.pieces.len() < 2 {
+            return (self, None);
+        }
+        let second_half = self.pieces.split_off(self.pieces.len() / 2);
+        (self, Some(RefProducer { pieces: second_half }))
+    }
+
+    fn fold_with<F: Folder<Self::Item>>(self, mut folder: F) -> F {
+        for piece in self.pieces {
+            folder = match piece {
+                Piece::Subtree(node) => folder.consume_iter(iter::Iter::from_root(Some(node))),
+                Piece::Value(value) => folder.consume(value),
+            };
+            if folder.full() {
+                break;
+            }
+        }
+        folder
+    }
+}
+
+impl<T: Ord + Send> UnindexedProducer for OwnedProducer<T> {
+    type Item = T;
+
+    fn split(mut self) -> (Self, Option<Self>) {
+        if let [OwnedPiece::Subtree(_)] = self.pieces[..] {
+            if let Some(OwnedPiece::Subtree(node)) = self.pieces.pop() {
+                let node = Box::into_inner(node);
+                if let Some(left) = node.left {
+                    self.pieces.push(OwnedPiece::Subtree(left));
+                }
+                self.pieces.push(OwnedPiece::Value(node.value));
+                if let Some(right) = node.right {
+                    self.pieces.push(OwnedPiece::Subtree(right));
+                }
+            }
+        }
+        if self.pieces.len() < 2 {
+            return (self, None);
+        }
+        let second_half = self.pieces.split_off(self.pieces.len() / 2);
+        (self, Some(OwnedProducer { pieces: second_half }))
+    }
+
+    fn fold_with<F: Folder<Self::Item>>(self, mut folder: F) -> F {
+        for piece in self.pieces {
+            folder = match piece {
+                OwnedPiece::Subtree(node) => folder.consume_iter(iter::IntoIter::from_root(Some(node))),
+                OwnedPiece::Value(value) => folder.consume(value),
+            };
+            if folder.full() {
+                break;
+            }
+        }
+        folder
+    }
+}
+
+impl<'a, T: Ord + Sync> ParallelIterator for ParIter<'a, T> {
+    type Item = &'a T;
+
+    fn drive_unindexed<C: UnindexedConsumer<Self::Item>>(self, consumer: C) -> C::Result {
+        let pieces = self.root.map(Piece::Subtree).into_iter().collect();
+        bridge_unindexed(RefProducer { pieces }, consumer)
+    }
+}
+
+impl<T: Ord + Send> ParallelIterator for IntoParIter<T> {
+    type Item = T;
+
+    fn drive_unindexed<C: UnindexedConsumer<Self::Item>>(self, consumer: C) -> C::Result {
+        let pieces = self.root.map(OwnedPiece::Subtree).into_iter().collect();
+        bridge_unindexed(OwnedProducer { pieces }, consumer)
+    }
+}
+
+impl<'a, T: Ord + Sync> IntoParallelIterator for &'a RBTree<T> {
+    type Item = &'a T;
+    type Iter = ParIter<'a, T>;
+
+    fn into_par_iter(self) -> Self::Iter {
+        ParIter { root: self.root.as_deref() }
+    }
+}
+
+impl<T: Ord + Send> IntoParallelIterator for RBTree<T> {
+    type Item = T;
+    type Iter = IntoParIter<T>;
+
+    fn into_par_iter(self) -> Self::Iter {
+        IntoParIter { root: self.root }
+    }
+}
+
+/// Inserts a batch that is small next to the tree one value at a time. A larger one is sorted in
+/// parallel and merged with the existing values, then the tree is rebuilt in O(n + m).
+impl<T: Ord + Send> ParallelExtend<T> for RBTree<T> {
+    fn par_extend<I: IntoParallelIterator<Item = T>>(&mut self, par_iter: I) {
+        let mut added: Vec<T> = par_iter.into_par_iter().collect();
+        let len = self.len();
+        // every insert costs about log2(len) steps
+        let depth = (usize::BITS - len.leading_zeros()) as usize;
+        if added.len().saturating_mul(depth) < len {
+            for value in added {
+                self.insert(value);
+            }
+            return;
+        }
+        added.par_sort();
+
+        let mut merged = Vec::with_capacity(len + added.len());
+        // where the added values end up, to notify about them once they are in the tree
+        let mut positions = Vec::with_capacity(added.len());
+        let mut existing = self.take_all().peekable();
+        let mut added = added.into_iter().peekable();
+        while let (Some(old), Some(new)) = (existing.peek(), added.peek()) {
+            // existing values go first among equals, like repeated inserts would place them
+            if new < old {
+                positions.push(merged.len());
+                merged.extend(added.next());
+            } else {
+                merged.extend(existing.next());
+            }
+        }
+        merged.extend(existing);
+        positions.extend(merged.len()..merged.len() + added.len());
+        merged.extend(added);
+        self.root = bulk::build_from_sorted(merged, &Global);
+
+        if !self.observers.is_empty() {
+            let mut positions = positions.into_iter().peekable();
+            for (position, value) in iter::Iter::from_root(self.root.as_deref()).enumerate() {
+                if positions.next_if_eq(&position).is_some() {
+                    self.observers.notify(Event::Insert(value));
+                }
+            }
+        }
+    }
+}
+
+impl<T: Ord + Send> FromParallelIterator<T> for RBTree<T> {
+    fn from_par_iter<I: IntoParallelIterator<Item = T>>(par_iter: I) -> Self {
+        let mut tree = RBTree::new();
+        tree.par_extend(par_iter);
+        tree
+    }
+}
+
+impl<T: Ord + Clone + Send + Sync> RBTree<T> {
+    /// Builds a balanced tree from sorted values, building the subtrees in parallel.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `values` is not sorted.
+    pub fn par_from_sorted(values: &[T]) -> RBTree<T> {
+        assert!(values.par_windows(2).all(|pair| pair[0] <= pair[1]), "par_from_sorted needs sorted values");
+        let mut tree = RBTree::new();
+        tree.root = par_build_subtree(values, 0, bulk::red_depth(values.len()));
+        tree
+    }
+}
+
+fn par_build_subtree<T: Ord + Clone + Send + Sync>(values: &[T], depth: usize, red_depth: usize) -> Option<Box<Node<T>>> {
+    if values.is_empty() {
+        return None;
+    }
+    let middle = (values.len() - 1) / 2;
+    let (left, right) = if values.len() < SEQUENTIAL_BUILD_LEN {
+        (par_build_subtree(&values[..middle], depth + 1, red_depth), par_build_subtree(&values[middle + 1..], depth + 1, red_depth))
+    } else {
+        ::rayon::join(
+            || par_build_subtree(&values[..middle], depth + 1, red_depth),
+            || par_build_subtree(&values[middle + 1..], depth + 1, red_depth),
+        )
+    };
+    let color = if depth == red_depth { Color::Red } else { Color::Black };
+    Some(Box::new(Node { color, value: values[middle].clone(), left, right, size: values.len() }))
+}
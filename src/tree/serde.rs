@@ -0,0 +1,86 @@
+use core::marker::PhantomData;
+
+use ::serde::de::{Error, MapAccess, SeqAccess, Visitor};
+use ::serde::{Deserialize, Deserializer, Serialize, Serializer};
+
+use super::map::Entry;
+use super::*;
+
+/// Serialized as a sequence of the values in order.
+impl<T: Ord + Serialize, A: Allocator> Serialize for RBTree<T, A> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_seq(self.iter())
+    }
+}
+
+/// Expects the values in order and builds the tree in O(n). Unsorted input is rejected.
+/// Equal values are allowed, since the tree keeps duplicates.
+impl<'de, T: Ord + Deserialize<'de>, A: Allocator + Clone + Default> Deserialize<'de> for RBTree<T, A> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_seq(SortedSeqVisitor(PhantomData))
+    }
+}
+
+struct SortedSeqVisitor<T, A>(PhantomData<(T, A)>);
+
+impl<'de, T: Ord + Deserialize<'de>, A: Allocator + Clone + Default> Visitor<'de> for SortedSeqVisitor<T, A> {
+    type Value = RBTree<T, A>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a sorted sequence")
+    }
+
+    fn visit_seq<S: SeqAccess<'de>>(self, mut seq: S) -> Result<Self::Value, S::Error> {
+        // the size hint comes from the input, so don't trust it with huge allocations
+        let mut values: Vec<T> = Vec::with_capacity(seq.size_hint().unwrap_or(0).min(4096));
+        while let Some(value) = seq.next_element()? {
+            if values.last().is_some_and(|previous| value < *previous) {
+                return Err(S::Error::custom(format_args!("sequence is not sorted, element {} is smaller than the one before it", values.len())));
+            }
+            values.push(value);
+        }
+        let mut tree = RBTree::new_in(A::default());
+        tree.root = bulk::build_from_sorted(values, &tree.alloc);
+        Ok(tree)
+    }
+}
+
+/// Serialized as a map from the keys to their values, in order of the keys.
+impl<K: Ord + Serialize, V: Serialize> Serialize for RBTreeMap<K, V> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.collect_map(self.iter())
+    }
+}
+
+/// Takes the keys in any order and builds the map in O(n) if they come sorted, O(n log n)
+/// otherwise. A key that shows up twice is rejected.
+impl<'de, K: Ord + Deserialize<'de>, V: Deserialize<'de>> Deserialize<'de> for RBTreeMap<K, V> {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserializer.deserialize_map(MapVisitor(PhantomData))
+    }
+}
+
+struct MapVisitor<K, V>(PhantomData<(K, V)>);
+
+impl<'de, K: Ord + Deserialize<'de>, V: Deserialize<'de>> Visitor<'de> for MapVisitor<K, V> {
+    type Value = RBTreeMap<K, V>;
+
+    fn expecting(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        formatter.write_str("a map")
+    }
+
+    fn visit_map<M: MapAccess<'de>>(self, mut access: M) -> Result<Self::Value, M::Error> {
+        let mut entries: Vec<Entry<K, V>> = Vec::with_capacity(access.size_hint().unwrap_or(0).min(4096));
+        while let Some((key, value)) = access.next_entry()? {
+            entries.push(Entry { key, value });
+        }
+        // finds the runs, so sorted input is only walked once
+        entries.sort();
+        if let Some(at) = entries.windows(2).position(|pair| pair[0] == pair[1]) {
+            return Err(M::Error::custom(format_args!("duplicate key in map, {} keys are smaller than it", at)));
+        }
+        let mut map = RBTreeMap::new();
+        map.tree.root = bulk::build_from_sorted(entries, &Global);
+        Ok(map)
+    }
+}
@@ -0,0 +1,468 @@
+// Random operation sequences applied to both the tree and a BTreeMap counting duplicates,
+// comparing the results and checking the invariants after every step.
+
+use std::collections::BTreeMap;
+use std::ops::{Bound, RangeBounds};
+
+use proptest::prelude::*;
+
+use super::tools::*;
+use super::*;
+use crate::balanced::{Avl, Balance, BalancedTree, RedBlack, Wavl};
+
+#[derive(Clone, Debug)]
+enum Op {
+    Insert(i32),
+    Delete(i32),
+    Contains(i32),
+    Iterate,
+}
+
+// A small value range, so duplicates and deletes of present values are common.
+fn op() -> impl Strategy<Value = Op> {
+    prop_oneof![
+        4 => (0..64).prop_map(Op::Insert),
+        3 => (0..64).prop_map(Op::Delete),
+        2 => (0..64).prop_map(Op::Contains),
+        1 => Just(Op::Iterate),
+    ]
+}
+
+fn expected_values(model: &BTreeMap<i32, usize>) -> Vec<i32> {
+    model.iter().flat_map(|(value, count)| std::iter::repeat_n(*value, *count)).collect()
+}
+
+fn assert_invariants(tree: &RBTree<i32>, model: &BTreeMap<i32, usize>) {
+    assert_no_red_violations(tree);
+    assert_no_black_violations(tree);
+    assert_tree_size(tree, model.values().sum());
+    assert_eq!(tree.validate(), Ok(()));
+    assert_eq!(tree.len(), model.values().sum());
+}
+
+// The trees under test, so every one of them runs the same operation sequences.
+trait Subject {
+    fn insert(&mut self, value: i32);
+    fn delete(&mut self, value: i32) -> bool;
+    fn contains(&self, value: i32) -> bool;
+    fn values(&self) -> Vec<i32>;
+    fn check(&self, model: &BTreeMap<i32, usize>);
+}
+
+impl Subject for RBTree<i32> {
+    fn insert(&mut self, value: i32) {
+        RBTree::insert(self, value);
+    }
+
+    fn delete(&mut self, value: i32) -> bool {
+        RBTree::delete(self, value)
+    }
+
+    fn contains(&self, value: i32) -> bool {
+        RBTree::contains(self, value)
+    }
+
+    fn values(&self) -> Vec<i32> {
+        self.iter().copied().collect()
+    }
+
+    fn check(&self, model: &BTreeMap<i32, usize>) {
+        assert_invariants(self, model);
+    }
+}
+
+impl Subject for LLRBTree<i32> {
+    fn insert(&mut self, value: i32) {
+        LLRBTree::insert(self, value);
+    }
+
+    fn delete(&mut self, value: i32) -> bool {
+        LLRBTree::delete(self, value)
+    }
+
+    fn contains(&self, value: i32) -> bool {
+        LLRBTree::contains(self, value)
+    }
+
+    fn values(&self) -> Vec<i32> {
+        self.iter().copied().collect()
+    }
+
+    fn check(&self, model: &BTreeMap<i32, usize>) {
+        assert_eq!(self.validate(), Ok(()));
+        assert_eq!(self.len(), model.values().sum());
+    }
+}
+
+impl<P: Balance> Subject for BalancedTree<i32, P> {
+    fn insert(&mut self, value: i32) {
+        BalancedTree::insert(self, value);
+    }
+
+    fn delete(&mut self, value: i32) -> bool {
+        BalancedTree::delete(self, value)
+    }
+
+    fn contains(&self, value: i32) -> bool {
+        BalancedTree::contains(self, &value)
+    }
+
+    fn values(&self) -> Vec<i32> {
+        self.iter().copied().collect()
+    }
+
+    fn check(&self, model: &BTreeMap<i32, usize>) {
+        assert_eq!(self.validate(), Ok(()));
+        assert_eq!(self.len(), model.values().sum());
+    }
+}
+
+// The lookups, order statistics and bulk removals the trees share through their subtree sizes.
+trait Ordered: Subject {
+    fn get(&self, value: i32) -> Option<&i32>;
+    fn select(&self, index: usize) -> Option<&i32>;
+    fn rank(&self, value: i32) -> usize;
+    fn nearest(&self, query: i32) -> Option<&i32>;
+    fn k_nearest(&self, query: i32, k: usize) -> Vec<&i32>;
+    fn remove_range(&mut self, range: (Bound<i32>, Bound<i32>)) -> usize;
+    fn retain(&mut self, f: &mut dyn FnMut(&i32) -> bool);
+    // The first `count` values `pred` holds for, leaving the others in the tree.
+    fn extract_first(&mut self, pred: &mut dyn FnMut(&i32) -> bool, count: usize) -> Vec<i32>;
+}
+
+fn distance(a: &i32, b: &i32) -> i32 {
+    (a - b).abs()
+}
+
+impl Ordered for RBTree<i32> {
+    fn get(&self, value: i32) -> Option<&i32> {
+        RBTree::get(self, &value)
+    }
+
+    fn select(&self, index: usize) -> Option<&i32> {
+        RBTree::select(self, index)
+    }
+
+    fn rank(&self, value: i32) -> usize {
+        RBTree::rank(self, &value)
+    }
+
+    fn nearest(&self, query: i32) -> Option<&i32> {
+        RBTree::nearest(self, &query, distance)
+    }
+
+    fn k_nearest(&self, query: i32, k: usize) -> Vec<&i32> {
+        RBTree::k_nearest(self, &query, k, distance)
+    }
+
+    fn remove_range(&mut self, range: (Bound<i32>, Bound<i32>)) -> usize {
+        RBTree::remove_range(self, range)
+    }
+
+    fn retain(&mut self, f: &mut dyn FnMut(&i32) -> bool) {
+        RBTree::retain(self, f)
+    }
+
+    fn extract_first(&mut self, pred: &mut dyn FnMut(&i32) -> bool, count: usize) -> Vec<i32> {
+        self.extract_if(pred).take(count).collect()
+    }
+}
+
+impl Ordered for LLRBTree<i32> {
+    fn get(&self, value: i32) -> Option<&i32> {
+        LLRBTree::get(self, &value)
+    }
+
+    fn select(&self, index: usize) -> Option<&i32> {
+        LLRBTree::select(self, index)
+    }
+
+    fn rank(&self, value: i32) -> usize {
+        LLRBTree::rank(self, &value)
+    }
+
+    fn nearest(&self, query: i32) -> Option<&i32> {
+        LLRBTree::nearest(self, &query, distance)
+    }
+
+    fn k_nearest(&self, query: i32, k: usize) -> Vec<&i32> {
+        LLRBTree::k_nearest(self, &query, k, distance)
+    }
+
+    fn remove_range(&mut self, range: (Bound<i32>, Bound<i32>)) -> usize {
+        LLRBTree::remove_range(self, range)
+    }
+
+    fn retain(&mut self, f: &mut dyn FnMut(&i32) -> bool) {
+        LLRBTree::retain(self, f)
+    }
+
+    fn extract_first(&mut self, pred: &mut dyn FnMut(&i32) -> bool, count: usize) -> Vec<i32> {
+        self.extract_if(pred).take(count).collect()
+    }
+}
+
+impl<P: Balance> Ordered for BalancedTree<i32, P> {
+    fn get(&self, value: i32) -> Option<&i32> {
+        BalancedTree::get(self, &value)
+    }
+
+    fn select(&self, index: usize) -> Option<&i32> {
+        BalancedTree::select(self, index)
+    }
+
+    fn rank(&self, value: i32) -> usize {
+        BalancedTree::rank(self, &value)
+    }
+
+    fn nearest(&self, query: i32) -> Option<&i32> {
+        BalancedTree::nearest(self, &query, distance)
+    }
+
+    fn k_nearest(&self, query: i32, k: usize) -> Vec<&i32> {
+        BalancedTree::k_nearest(self, &query, k, distance)
+    }
+
+    fn remove_range(&mut self, range: (Bound<i32>, Bound<i32>)) -> usize {
+        BalancedTree::remove_range(self, range)
+    }
+
+    fn retain(&mut self, f: &mut dyn FnMut(&i32) -> bool) {
+        BalancedTree::retain(self, f)
+    }
+
+    fn extract_first(&mut self, pred: &mut dyn FnMut(&i32) -> bool, count: usize) -> Vec<i32> {
+        self.extract_if(pred).take(count).collect()
+    }
+}
+
+fn check_order_statistics<S: Ordered>(tree: &S, expected: &[i32]) -> Result<(), TestCaseError> {
+    for (index, value) in expected.iter().enumerate() {
+        prop_assert_eq!(tree.select(index), Some(value));
+        prop_assert_eq!(tree.rank(*value), expected.partition_point(|other| other < value));
+        prop_assert_eq!(tree.get(*value), Some(value));
+    }
+    prop_assert_eq!(tree.select(expected.len()), None);
+    Ok(())
+}
+
+fn balanced_matches_btreemap<P: Balance>(ops: Vec<Op>) -> Result<(), TestCaseError> {
+    let mut tree = BalancedTree::<i32, P>::new();
+    let model = apply_ops(&mut tree, ops)?;
+    let expected = expected_values(&model);
+    check_order_statistics(&tree, &expected)?;
+    for start in (0..64).step_by(7) {
+        let range = (Bound::Excluded(start), Bound::Included(start + 9));
+        let expected_range: Vec<i32> = expected.iter().copied().filter(|value| range.contains(value)).collect();
+        prop_assert_eq!(tree.range(range).copied().collect::<Vec<i32>>(), expected_range);
+    }
+    prop_assert_eq!(tree.into_iter().collect::<Vec<i32>>(), expected);
+    Ok(())
+}
+
+fn filled<S: Ordered>(mut tree: S, values: Vec<i32>) -> (S, BTreeMap<i32, usize>) {
+    let mut model: BTreeMap<i32, usize> = BTreeMap::new();
+    for value in values {
+        tree.insert(value);
+        *model.entry(value).or_insert(0) += 1;
+    }
+    (tree, model)
+}
+
+fn nearest_matches_scan<S: Ordered>(tree: S, values: Vec<i32>, query: i32, k: usize) -> Result<(), TestCaseError> {
+    let (tree, _) = filled(tree, values.clone());
+    // a stable sort keeps equally distant values in ascending order
+    let mut expected = values;
+    expected.sort();
+    expected.sort_by_key(|value| distance(value, &query));
+
+    let nearest: Vec<i32> = tree.k_nearest(query, k).into_iter().copied().collect();
+    prop_assert_eq!(&nearest[..], &expected[..k.min(expected.len())]);
+    prop_assert_eq!(tree.nearest(query), expected.first());
+    Ok(())
+}
+
+fn remove_range_matches<S: Ordered>(tree: S, values: Vec<i32>, range: (Bound<i32>, Bound<i32>)) -> Result<(), TestCaseError> {
+    let (mut tree, mut model) = filled(tree, values);
+    let removed = tree.remove_range(range);
+    let expected_removed: usize = model.range(range).map(|(_, count)| count).sum();
+    model.retain(|value, _| !range.contains(value));
+
+    prop_assert_eq!(removed, expected_removed);
+    tree.check(&model);
+    prop_assert_eq!(tree.values(), expected_values(&model));
+    Ok(())
+}
+
+fn extract_and_retain_match<S: Ordered>(tree: S, values: Vec<i32>, modulus: i32, count: usize, cut: i32) -> Result<(), TestCaseError> {
+    let (mut tree, model) = filled(tree, values);
+    let mut expected = expected_values(&model);
+
+    let extracted = tree.extract_first(&mut |value| value % modulus == 0, count);
+    let expected_extracted: Vec<i32> = expected.iter().copied().filter(|value| value % modulus == 0).take(count).collect();
+    prop_assert_eq!(&extracted, &expected_extracted);
+    for value in extracted.iter() {
+        let at = expected.iter().position(|other| other == value).unwrap();
+        expected.remove(at);
+    }
+    prop_assert_eq!(tree.values(), expected.clone());
+
+    tree.retain(&mut |value| *value < cut || value % 2 == 0);
+    expected.retain(|value| *value < cut || value % 2 == 0);
+    let mut model: BTreeMap<i32, usize> = BTreeMap::new();
+    for value in expected.iter() {
+        *model.entry(*value).or_insert(0) += 1;
+    }
+    tree.check(&model);
+    prop_assert_eq!(tree.values(), expected);
+    Ok(())
+}
+
+// Returns the model, so the caller can compare what the tree turns into.
+fn apply_ops<S: Subject>(tree: &mut S, ops: Vec<Op>) -> Result<BTreeMap<i32, usize>, TestCaseError> {
+    let mut model: BTreeMap<i32, usize> = BTreeMap::new();
+
+    for op in ops {
+        match op {
+            Op::Insert(value) => {
+                tree.insert(value);
+                *model.entry(value).or_insert(0) += 1;
+            },
+            Op::Delete(value) => {
+                let expected = match model.get_mut(&value) {
+                    Some(count) => {
+                        *count -= 1;
+                        if *count == 0 {
+                            model.remove(&value);
+                        }
+                        true
+                    },
+                    None => false,
+                };
+                prop_assert_eq!(tree.delete(value), expected);
+            },
+            Op::Contains(value) => {
+                prop_assert_eq!(tree.contains(value), model.contains_key(&value));
+            },
+            Op::Iterate => {
+                prop_assert_eq!(tree.values(), expected_values(&model));
+            },
+        }
+        tree.check(&model);
+    }
+    Ok(model)
+}
+
+proptest! {
+    #[test]
+    fn matches_btreemap(ops in prop::collection::vec(op(), 0..300), top_down in any::<bool>()) {
+        let mut tree = RBTree::new();
+        tree.set_mode(if top_down { UpdateMode::TopDown } else { UpdateMode::BottomUp });
+        let model = apply_ops(&mut tree, ops)?;
+        let expected = expected_values(&model);
+        check_order_statistics(&tree, &expected)?;
+        prop_assert_eq!(tree.into_iter().collect::<Vec<i32>>(), expected);
+    }
+
+    #[test]
+    fn llrb_matches_btreemap(ops in prop::collection::vec(op(), 0..300)) {
+        let mut tree = LLRBTree::new();
+        let model = apply_ops(&mut tree, ops)?;
+        let expected = expected_values(&model);
+        check_order_statistics(&tree, &expected)?;
+        prop_assert_eq!(tree.into_iter().collect::<Vec<i32>>(), expected);
+    }
+
+    #[test]
+    fn policies_match_btreemap(ops in prop::collection::vec(op(), 0..300)) {
+        balanced_matches_btreemap::<RedBlack>(ops.clone())?;
+        balanced_matches_btreemap::<Avl>(ops.clone())?;
+        balanced_matches_btreemap::<Wavl>(ops)?;
+    }
+
+    #[test]
+    fn k_nearest_matches_scan(values in prop::collection::vec(0..64, 0..100), query in -8..72, k in 0..20usize) {
+        nearest_matches_scan(RBTree::new(), values.clone(), query, k)?;
+        nearest_matches_scan(LLRBTree::new(), values.clone(), query, k)?;
+        nearest_matches_scan(BalancedTree::<i32, RedBlack>::new(), values.clone(), query, k)?;
+        nearest_matches_scan(BalancedTree::<i32, Avl>::new(), values.clone(), query, k)?;
+        nearest_matches_scan(BalancedTree::<i32, Wavl>::new(), values, query, k)?;
+    }
+
+    #[test]
+    fn range_mut_matches_btreemap(keys in prop::collection::vec(0..64, 0..100), start in 0..64, len in 0..32, reverse in any::<bool>()) {
+        let end = start + len;
+        let mut map: RBTreeMap<i32, i32> = keys.iter().map(|key| (*key, 0)).collect();
+        let mut model: BTreeMap<i32, i32> = keys.iter().map(|key| (*key, 0)).collect();
+        let range = (core::ops::Bound::Excluded(start), core::ops::Bound::Included(end));
+
+        let keys: Vec<i32> = if reverse {
+            map.range_mut(range).rev().map(|(key, value)| { *value += 1; *key }).collect()
+        } else {
+            map.range_mut(range).map(|(key, value)| { *value += 1; *key }).collect()
+        };
+        let mut expected: Vec<i32> = model.range_mut(range).map(|(key, value)| { *value += 1; *key }).collect();
+        if reverse {
+            expected.reverse();
+        }
+        prop_assert_eq!(keys, expected);
+        prop_assert_eq!(map.into_iter().collect::<Vec<_>>(), model.into_iter().collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn rollback_keeps_shape(values in prop::collection::vec(0..64, 0..200), ops in prop::collection::vec((any::<bool>(), 0..64), 0..60), top_down in any::<bool>()) {
+        let mut tree = RBTree::new();
+        tree.set_mode(if top_down { UpdateMode::TopDown } else { UpdateMode::BottomUp });
+        for (tag, value) in values.into_iter().enumerate() {
+            tree.insert(Tagged(value, tag));
+        }
+        let label = |value: &Tagged| (value.0, value.1);
+        let before = shape(&tree, label);
+
+        let result: Result<(), ()> = tree.transaction(|tx| {
+            for (tag, (insert, value)) in ops.into_iter().enumerate() {
+                if insert {
+                    tx.insert(Tagged(value, 1000 + tag));
+                } else {
+                    tx.delete(Tagged(value, 0));
+                }
+            }
+            Err(())
+        });
+
+        prop_assert!(result.is_err());
+        prop_assert_eq!(shape(&tree, label), before);
+        prop_assert_eq!(tree.validate(), Ok(()));
+    }
+
+    #[test]
+    fn remove_range_matches_btreemap(values in prop::collection::vec(0..64, 0..300), start in 0..64, len in 0..8, top_down in any::<bool>()) {
+        let mut tree = RBTree::new();
+        tree.set_mode(if top_down { UpdateMode::TopDown } else { UpdateMode::BottomUp });
+        let range = (Bound::Excluded(start), Bound::Included(start + len));
+        remove_range_matches(tree, values.clone(), range)?;
+        remove_range_matches(LLRBTree::new(), values.clone(), range)?;
+        remove_range_matches(BalancedTree::<i32, RedBlack>::new(), values.clone(), range)?;
+        remove_range_matches(BalancedTree::<i32, Avl>::new(), values.clone(), range)?;
+        remove_range_matches(BalancedTree::<i32, Wavl>::new(), values, range)?;
+    }
+
+    #[test]
+    fn rebuilds_match_btreemap(values in prop::collection::vec(0..64, 0..300), cut in 0..64) {
+        let range = (Bound::Included(cut), Bound::Unbounded);
+        remove_range_matches(RBTree::new(), values.clone(), range)?;
+        remove_range_matches(LLRBTree::new(), values.clone(), range)?;
+        remove_range_matches(BalancedTree::<i32, RedBlack>::new(), values.clone(), range)?;
+        remove_range_matches(BalancedTree::<i32, Avl>::new(), values.clone(), range)?;
+        remove_range_matches(BalancedTree::<i32, Wavl>::new(), values, range)?;
+    }
+
+    #[test]
+    fn extract_and_retain_match_btreemap(values in prop::collection::vec(0..64, 0..300), modulus in 1..8, count in 0..40usize, cut in 0..64) {
+        extract_and_retain_match(RBTree::new(), values.clone(), modulus, count, cut)?;
+        extract_and_retain_match(LLRBTree::new(), values.clone(), modulus, count, cut)?;
+        extract_and_retain_match(BalancedTree::<i32, RedBlack>::new(), values.clone(), modulus, count, cut)?;
+        extract_and_retain_match(BalancedTree::<i32, Avl>::new(), values.clone(), modulus, count, cut)?;
+        extract_and_retain_match(BalancedTree::<i32, Wavl>::new(), values, modulus, count, cut)?;
+    }
+}
@@ -0,0 +1,322 @@
+use super::*;
+
+/// How `RBTree::insert` and `RBTree::take` restore the invariants, see `RBTree::set_mode`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum UpdateMode {
+    /// Walk down to the node, then fix the tree on the way back up. Only touches what needs
+    /// fixing, but keeps the whole path on the call stack.
+    #[default]
+    BottomUp,
+    /// Recolor and rotate on the way down, so every operation is a single pass with O(1)
+    /// extra memory. Rebalances more eagerly than bottom-up.
+    TopDown,
+}
+
+type Link<T, A> = Option<Box<Node<T, A>, A>>;
+
+fn is_red<T: Ord, A: Allocator>(node_or_leaf: Option<&Node<T, A>>) -> bool {
+    get_color(node_or_leaf) == Color::Red
+}
+
+// Follows `path` down from the node in `slot`, returning the slot at its end.
+fn descend<'a, T: Ord, A: Allocator>(mut slot: &'a mut Link<T, A>, path: &[Direction]) -> &'a mut Link<T, A> {
+    for dir in path {
+        slot = slot.as_mut().expect("The path leads through restructured nodes").link(*dir);
+    }
+    slot
+}
+
+// Like `descend`, but counts the insert or delete into the size of every node on the way, which
+// the walk does once it is sure the change ends up below them.
+fn pass<'a, T: Ord, A: Allocator>(mut slot: &'a mut Link<T, A>, path: &[Direction], inserting: bool) -> &'a mut Link<T, A> {
+    for dir in path {
+        let node = slot.as_mut().expect("The path leads through restructured nodes");
+        if inserting {
+            node.size += 1;
+        } else {
+            node.size -= 1;
+        }
+        slot = node.link(*dir);
+    }
+    slot
+}
+
+// Makes a node with two red children red and the children black. Returns whether it did.
+fn split<T: Ord, A: Allocator>(node: &mut Node<T, A>, observers: &mut Observers<T>) -> bool {
+    if !is_red(node.left.as_deref()) || !is_red(node.right.as_deref()) {
+        return false;
+    }
+    observers.fixup(FixupCase::InsertRedUncle);
+    set_color(node.left.as_mut().unwrap(), Color::Black, observers);
+    set_color(node.right.as_mut().unwrap(), Color::Black, observers);
+    set_color(node, Color::Red, observers);
+    true
+}
+
+impl<T: Ord, A: Allocator> RBTree<T, A> {
+    /// Selects how inserts and deletes rebalance the tree. Both modes keep the same invariants,
+    /// so the mode can be switched at any time.
+    pub fn set_mode(&mut self, mode: UpdateMode) {
+        self.mode = mode;
+    }
+
+    pub fn mode(&self) -> UpdateMode {
+        self.mode
+    }
+}
+
+impl<T: Ord, A: Allocator + Clone> RBTree<T, A> {
+    // Splits every node with two red children on the way down, which leaves the new node a
+    // black parent or a red parent with a black sibling. The latter is fixed by a rotation
+    // at the grandparent, so the walk keeps a cursor on the grandparent's slot.
+    pub(super) fn insert_top_down(&mut self, value: T) {
+        let observers = &mut self.observers;
+        let new_node = Box::new_in(Node::new(Color::Red, value), self.alloc.clone());
+        let root = self.root.as_mut().expect("insert handles the empty tree");
+        if split(root, observers) {
+            observers.fixup(FixupCase::InsertRedRoot);
+            set_color(root, Color::Black, observers);
+        }
+        let mut dir = Self::insert_direction(&new_node.value, root, observers);
+        match root.get_child(dir) {
+            Some(child) => {
+                if split(child, observers) {
+                    observers.fixup(FixupCase::InsertBlackParent);
+                }
+            },
+            None => {
+                root.set_child(dir, new_node);
+                root.size += 1;
+                observers.notify(Event::Insert(&root.child(dir).unwrap().value));
+                observers.fixup(FixupCase::InsertBlackParent);
+                observers.counters.finish();
+                return;
+            },
+        }
+
+        // `slot` holds the grandparent, `dir` leads to the parent, which always exists. Only the
+        // nodes above `slot` count the new node yet.
+        let mut slot = &mut self.root;
+        loop {
+            let parent = slot.as_deref().unwrap().child(dir).expect("The walk only moves down to existing parents");
+            let next_dir = Self::insert_direction(&new_node.value, parent, observers);
+            let parent_is_red = !parent.is_black();
+            let next = match parent.child(next_dir) {
+                Some(next) => next,
+                None => {
+                    let parent = descend(slot, &[dir]).as_mut().unwrap();
+                    parent.set_child(next_dir, new_node);
+                    parent.size += 1;
+                    observers.notify(Event::Insert(&parent.child(next_dir).unwrap().value));
+                    slot.as_mut().unwrap().size += 1;
+                    if parent_is_red {
+                        Self::rotate_red_parent(slot, dir, next_dir, observers);
+                    } else {
+                        observers.fixup(FixupCase::InsertBlackParent);
+                    }
+                    break;
+                },
+            };
+            let splits = is_red(next.left.as_deref()) && is_red(next.right.as_deref());
+
+            if splits {
+                split(descend(slot, &[dir, next_dir]).as_mut().unwrap(), observers);
+                if parent_is_red {
+                    Self::rotate_red_parent(slot, dir, next_dir, observers);
+                    // the new top is black with red children, carry on from there
+                    dir = Self::insert_direction(&new_node.value, slot.as_deref().unwrap(), observers);
+                    continue;
+                }
+                observers.fixup(FixupCase::InsertBlackParent);
+            }
+            slot = pass(slot, &[dir], true);
+            dir = next_dir;
+        }
+        observers.counters.finish();
+    }
+
+    fn insert_direction(value: &T, node: &Node<T, A>, observers: &mut Observers<T>) -> Direction {
+        observers.visit(&node.value);
+        observers.counters.compared(1);
+        if *value < node.value { Direction::Left } else { Direction::Right }
+    }
+
+    // The red child in `child_dir` of the red node in `dir` gets rotated above the grandparent in `slot`.
+    fn rotate_red_parent(slot: &mut Link<T, A>, dir: Direction, child_dir: Direction, observers: &mut Observers<T>) {
+        let rotation = if child_dir == dir {
+            observers.fixup(FixupCase::InsertOuterGrandchild);
+            RotationType::Single(dir.opposite())
+        } else {
+            observers.fixup(FixupCase::InsertInnerGrandchild);
+            RotationType::Double(dir.opposite())
+        };
+        let mut top = rotate(slot.take().unwrap(), rotation, observers);
+        set_color(&mut top, Color::Black, observers);
+        set_color(top.get_child(dir.opposite()).unwrap(), Color::Red, observers);
+        *slot = Some(top);
+    }
+
+    // Pushes a red node down the path, so that the node finally removed is red and nothing
+    // needs fixing afterwards. A node with two children is not removed itself: the walk goes
+    // on to its in-order predecessor, whose value takes its place.
+    pub(super) fn take_top_down<Q: ?Sized + Ord>(&mut self, value: &Q) -> Option<T>
+    where
+        T: Borrow<Q>,
+    {
+        let observers = &mut self.observers;
+        let mut found = false;
+        let root = match self.root.as_deref() {
+            Some(root) => root,
+            None => {
+                observers.counters.finish();
+                return None;
+            },
+        };
+        let (mut dir, root_is_found) = Self::delete_direction(value, root, &mut found, observers);
+        let rotates_root = !is_red(root.child(dir)) && is_red(root.child(dir.opposite()));
+
+        // `slot` holds the parent of the node the walk moves to next, the nodes above it no
+        // longer count the node that is removed
+        let mut slot = &mut self.root;
+        if rotates_root {
+            observers.fixup(FixupCase::DeleteRedSibling);
+            Self::rotate_red_child(slot, dir, observers);
+            slot = pass(slot, &[dir], false);
+        }
+        let mut parent_is_found = root_is_found;
+        let mut found_value = None;
+
+        loop {
+            let parent = slot.as_deref().unwrap();
+            let last = dir;
+            let node = match parent.child(last) {
+                Some(node) => node,
+                None => break,
+            };
+            let (next_dir, node_is_found) = Self::delete_direction(value, node, &mut found, observers);
+            let node_is_red = !node.is_black();
+            let next_is_red = is_red(node.child(next_dir));
+            let other_is_red = is_red(node.child(next_dir.opposite()));
+            let nephews = parent.child(last.opposite()).map(|sibling| (is_red(sibling.child(last)), is_red(sibling.child(last.opposite()))));
+
+            // the path from `slot` to the node once it is red, and the depth the parent ended up at
+            let mut path = [last, next_dir];
+            let mut len = 1;
+            let mut parent_depth = 0;
+            if node_is_red || next_is_red {
+                // already red, or the next node is
+            } else if other_is_red {
+                observers.fixup(FixupCase::DeleteRedSibling);
+                Self::rotate_red_child(descend(slot, &[last]), next_dir, observers);
+                len = 2;
+            } else {
+                match nephews {
+                    Some((false, false)) => {
+                        observers.fixup(FixupCase::DeleteRedParent);
+                        let parent = slot.as_mut().unwrap();
+                        set_color(parent, Color::Black, observers);
+                        set_color(parent.get_child(last.opposite()).unwrap(), Color::Red, observers);
+                        set_color(parent.get_child(last).unwrap(), Color::Red, observers);
+                    },
+                    Some((close_is_red, _)) => {
+                        let rotation = if close_is_red {
+                            observers.fixup(FixupCase::DeleteCloseNephew);
+                            RotationType::Double(last)
+                        } else {
+                            observers.fixup(FixupCase::DeleteDistantNephew);
+                            RotationType::Single(last)
+                        };
+                        let mut top = rotate(slot.take().unwrap(), rotation, observers);
+                        set_color(top.get_child(last).unwrap().get_child(last).unwrap(), Color::Red, observers);
+                        set_color(&mut top, Color::Red, observers);
+                        set_color(top.left.as_mut().unwrap(), Color::Black, observers);
+                        set_color(top.right.as_mut().unwrap(), Color::Black, observers);
+                        *slot = Some(top);
+                        path = [last, last];
+                        len = 2;
+                        parent_depth = 1;
+                    },
+                    // only the root can be black without a sibling
+                    None => {},
+                }
+            }
+
+            if parent_is_found {
+                // From here on the walk stays below the found node, so its value can be held on to.
+                let found_node = pass(slot, &path[..parent_depth], false).as_mut().unwrap();
+                found_node.size -= 1;
+                let Node { value, left, .. } = &mut **found_node;
+                found_value = Some(value);
+                slot = pass(left, &path[parent_depth + 1..len], false);
+            } else {
+                slot = pass(slot, &path[..len], false);
+            }
+            parent_is_found = node_is_found;
+            dir = next_dir;
+        }
+
+        if !found {
+            // the walk counted a delete into the sizes that didn't happen
+            Self::undo_pass(&mut self.root, value);
+            if let Some(root) = self.root.as_mut() {
+                set_color(root, Color::Black, observers);
+            }
+            observers.counters.finish();
+            return None;
+        }
+        if let Some(found_value) = found_value {
+            core::mem::swap(found_value, &mut slot.as_mut().unwrap().value);
+        }
+        let node = Box::into_inner(slot.take().unwrap());
+        *slot = node.left.or(node.right);
+        observers.notify(Event::Delete(&node.value));
+        if let Some(root) = self.root.as_mut() {
+            set_color(root, Color::Black, observers);
+        }
+        observers.counters.finish();
+        Some(node.value)
+    }
+
+    // Adds back the node a delete that found nothing took off the sizes on its way down. That is
+    // every node on the search path but the last, where the walk stopped.
+    fn undo_pass<Q: ?Sized + Ord>(mut slot: &mut Link<T, A>, value: &Q)
+    where
+        T: Borrow<Q>,
+    {
+        while let Some(node) = slot {
+            let dir = if value < node.value.borrow() { Direction::Left } else { Direction::Right };
+            if node.child(dir).is_none() {
+                break;
+            }
+            node.size += 1;
+            slot = node.link(dir);
+        }
+    }
+
+    // Returns where the walk goes from `node`, and whether `node` holds the value. After the
+    // value is found the walk continues to its in-order predecessor.
+    fn delete_direction<Q: ?Sized + Ord>(value: &Q, node: &Node<T, A>, found: &mut bool, observers: &mut Observers<T>) -> (Direction, bool)
+    where
+        T: Borrow<Q>,
+    {
+        observers.visit(&node.value);
+        if *found {
+            return (Direction::Right, false);
+        }
+        if value == node.value.borrow() {
+            observers.counters.compared(1);
+            *found = true;
+            return (Direction::Left, true);
+        }
+        observers.counters.compared(2);
+        if value < node.value.borrow() { (Direction::Left, false) } else { (Direction::Right, false) }
+    }
+
+    // Rotates the red child of the black node in `slot` up, making the node red on the `dir` side.
+    fn rotate_red_child(slot: &mut Link<T, A>, dir: Direction, observers: &mut Observers<T>) {
+        let mut top = rotate(slot.take().unwrap(), RotationType::Single(dir), observers);
+        set_color(&mut top, Color::Black, observers);
+        set_color(top.get_child(dir).unwrap(), Color::Red, observers);
+        *slot = Some(top);
+    }
+}
@@ -0,0 +1,80 @@
+use super::*;
+
+/// One step of an insert or delete, recorded while tracing is on. See `RBTree::start_trace`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
+pub enum TraceEvent<T> {
+    /// A node compared against on the way down, including the walk to an in-order successor.
+    Visit(T),
+    Insert(T),
+    Delete(T),
+    /// The fixup case that applies at the current node.
+    Case(FixupCase),
+    /// The node holding `value` moved down to the `direction` side. A `Double` rotation first rotates its child the other way.
+    Rotate { value: T, kind: RotationKind, direction: Direction },
+    Recolor { value: T, color: Color },
+}
+
+/// The rebalancing cases, numbered as in the comments of the insert and delete fixups.
+///
+/// `UpdateMode::TopDown` reports the case whose recoloring or rotation it applies on the way down.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
+pub enum FixupCase {
+    /// The parent of the new or recolored node is black, nothing to fix.
+    InsertBlackParent,
+    /// Parent and uncle are red: both become black and the grandparent red.
+    InsertRedUncle,
+    /// Case 4 & 5: the node is an inner grandchild, needs a double rotation.
+    InsertInnerGrandchild,
+    /// Case 5: the node is an outer grandchild, needs a single rotation.
+    InsertOuterGrandchild,
+    /// The root ended up red and is made black.
+    InsertRedRoot,
+    /// Case 1: parent, sibling and nephews are black. The sibling becomes red and the check moves up.
+    DeleteAllBlack,
+    /// Case 2: the check moved up to the root, nothing left to fix.
+    DeleteReachedRoot,
+    /// Case 3: the sibling is red.
+    DeleteRedSibling,
+    /// Case 4: the parent is red, sibling and nephews are black.
+    DeleteRedParent,
+    /// Case 5 (+6): the close nephew is red.
+    DeleteCloseNephew,
+    /// Case 6: the distant nephew is red.
+    DeleteDistantNephew,
+}
+
+/// Whether a rotation is a single one or two in opposite directions.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
+pub enum RotationKind {
+    Single,
+    Double,
+}
+
+pub(crate) struct Recorder<T> {
+    pub events: Vec<TraceEvent<T>>,
+    // tracing needs owned values, the Clone bound is only known when it is started
+    pub clone: fn(&T) -> T,
+}
+
+impl<T: Ord + Clone, A: Allocator> RBTree<T, A> {
+    /// Starts recording every following insert and delete as a list of `TraceEvent`s.
+    /// A trace that was already being recorded is discarded.
+    pub fn start_trace(&mut self) {
+        self.observers.trace = Some(Recorder { events: Vec::new(), clone: T::clone });
+    }
+}
+
+impl<T: Ord, A: Allocator> RBTree<T, A> {
+    /// The events recorded since `start_trace`. Empty when not tracing.
+    pub fn trace(&self) -> &[TraceEvent<T>] {
+        self.observers.trace.as_ref().map_or(&[], |recorder| &recorder.events)
+    }
+
+    /// Stops tracing and returns the recorded events.
+    pub fn stop_trace(&mut self) -> Vec<TraceEvent<T>> {
+        self.observers.trace.take().map_or_else(Vec::new, |recorder| recorder.events)
+    }
+}
@@ -0,0 +1,286 @@
+use core::ops::Deref;
+
+use super::*;
+
+type Link<T, A> = Option<Box<Node<T, A>, A>>;
+
+// How far below the search path a bottom-up insert or delete can restructure the tree. A delete
+// that meets a red sibling rotates it up and then fixes the new sibling, which was a nephew, so
+// the deepest node it recolors or relinks is a grandchild of the sibling.
+const REACH: usize = 3;
+
+// A piece of the tree's shape in preorder. Values are named by their index in sorted order,
+// which rotations don't change.
+enum Piece {
+    Node(usize, Color),
+    // a subtree that no change reaches, by the index of its first value and its size
+    Subtree(usize, usize),
+}
+
+enum Undo<T> {
+    // the value at the index has to be taken out again
+    Inserted { index: usize, shape: Vec<Piece> },
+    // the value has to be put back at the index
+    Removed { index: usize, value: T, shape: Vec<Piece> },
+}
+
+/// A batch of changes to an `RBTree`, created by `RBTree::transaction`.
+///
+/// Reads go through `Deref` to the tree and see the changes made so far.
+/// If the transaction is dropped without being committed, every change is undone in reverse order.
+///
+/// Changes always rebalance bottom-up, whatever the tree's `UpdateMode`. Before each change the
+/// transaction records the shape of the part of the tree the change can restructure, and undoing
+/// the change puts that part back as it was, node colors included.
+pub struct Transaction<'a, T: Ord, A: Allocator + Clone = Global> {
+    tree: &'a mut RBTree<T, A>,
+    undo_log: Vec<Undo<T>>,
+    committed: bool,
+}
+
+impl<'a, T: Ord, A: Allocator + Clone> Transaction<'a, T, A> {
+    pub fn insert(&mut self, value: T) {
+        // equal values go right, so the new one ends up after all its copies
+        let mut path = Vec::new();
+        let mut index = 0;
+        let mut next = self.tree.root.as_deref();
+        while let Some(node) = next {
+            let dir = if value < node.value { Direction::Left } else { Direction::Right };
+            if dir == Direction::Right {
+                index += subtree_size(node.left.as_deref()) + 1;
+            }
+            path.push(dir);
+            next = node.child(dir);
+        }
+        let shape = self.capture(&path);
+        self.tree.insert_bottom_up(value);
+        self.undo_log.push(Undo::Inserted { index, shape });
+    }
+
+    pub fn delete(&mut self, value: T) -> bool {
+        match self.remove(&value) {
+            Some((index, removed, shape)) => {
+                self.undo_log.push(Undo::Removed { index, value: removed, shape });
+                true
+            },
+            None => false,
+        }
+    }
+
+    /// Like `RBTree::take`. The transaction keeps a clone to put back on rollback.
+    pub fn take(&mut self, value: &T) -> Option<T>
+    where
+        T: Clone,
+    {
+        let (index, removed, shape) = self.remove(value)?;
+        self.undo_log.push(Undo::Removed { index, value: removed.clone(), shape });
+        Some(removed)
+    }
+
+    // Finds the node a bottom-up delete takes the value from, the first equal one on the search
+    // path, and the successor it gets its new value from if it has two children.
+    fn remove(&mut self, value: &T) -> Option<(usize, T, Vec<Piece>)> {
+        let mut path = Vec::new();
+        let mut index = 0;
+        let mut next = self.tree.root.as_deref();
+        loop {
+            let node = next?;
+            if *value == node.value {
+                index += subtree_size(node.left.as_deref());
+                if let (Some(_), Some(mut successor)) = (&node.left, node.right.as_deref()) {
+                    path.push(Direction::Right);
+                    while let Some(left) = successor.left.as_deref() {
+                        path.push(Direction::Left);
+                        successor = left;
+                    }
+                }
+                break;
+            }
+            let dir = if *value < node.value { Direction::Left } else { Direction::Right };
+            if dir == Direction::Right {
+                index += subtree_size(node.left.as_deref()) + 1;
+            }
+            path.push(dir);
+            next = node.child(dir);
+        }
+        let shape = self.capture(&path);
+        let removed = self.tree.take_bottom_up(value).expect("the search above found the value");
+        Some((index, removed, shape))
+    }
+
+    fn capture(&self, path: &[Direction]) -> Vec<Piece> {
+        let mut shape = Vec::new();
+        capture(self.tree.root.as_deref(), 0, Some(path), REACH, &mut shape);
+        shape
+    }
+
+    fn rollback(&mut self) {
+        while let Some(undo) = self.undo_log.pop() {
+            self.undo(undo);
+        }
+    }
+
+    // The tree is exactly as the change left it, since every later change is undone already.
+    // Everything the change reached is taken apart and put together again in the recorded shape.
+    fn undo(&mut self, undo: Undo<T>) {
+        let (shape, restored) = match undo {
+            Undo::Inserted { index, shape } => (shape, Restored { inserted: Some(index), removed: None }),
+            Undo::Removed { index, value, shape } => (shape, Restored { inserted: None, removed: Some((index, value)) }),
+        };
+        let mut reached: Vec<usize> = shape.iter().filter_map(|piece| match piece {
+            Piece::Node(index, _) => Some(*index),
+            Piece::Subtree(..) => None,
+        }).collect();
+        reached.sort_unstable();
+
+        let mut parts = Parts { values: Vec::with_capacity(reached.len()), subtrees: Vec::new() };
+        let observers = &mut self.tree.observers;
+        let root = self.tree.root.take();
+        take_apart(root, 0, &restored, &reached, &mut parts, observers);
+        if let Some((index, value)) = restored.removed {
+            observers.notify(Event::Insert(&value));
+            let at = parts.values.partition_point(|(other, _)| *other < index);
+            parts.values.insert(at, (index, value));
+        }
+
+        let mut values: Vec<Option<T>> = parts.values.into_iter().map(|(_, value)| Some(value)).collect();
+        let mut pieces = shape.iter();
+        self.tree.root = put_together(&mut pieces, &reached, &mut values, &mut parts.subtrees, &self.tree.alloc);
+        debug_assert!(pieces.next().is_none() && values.iter().all(Option::is_none));
+    }
+}
+
+// The index the undone change inserted at or removed from.
+struct Restored<T> {
+    inserted: Option<usize>,
+    removed: Option<(usize, T)>,
+}
+
+impl<T> Restored<T> {
+    // The index a value had before the change, `None` for the inserted one.
+    fn before(&self, index: usize) -> Option<usize> {
+        match (self.inserted, &self.removed) {
+            (Some(inserted), _) if index == inserted => None,
+            (Some(inserted), _) if index > inserted => Some(index - 1),
+            (_, Some((removed, _))) if index >= *removed => Some(index + 1),
+            _ => Some(index),
+        }
+    }
+}
+
+// The values and untouched subtrees of a taken apart tree, in sorted order.
+struct Parts<T: Ord, A: Allocator> {
+    values: Vec<(usize, T)>,
+    subtrees: Vec<(usize, Link<T, A>)>,
+}
+
+fn capture<T: Ord, A: Allocator>(node: Option<&Node<T, A>>, offset: usize, path: Option<&[Direction]>, reach: usize, shape: &mut Vec<Piece>) {
+    let node = match node {
+        Some(node) if path.is_some() || reach > 0 => node,
+        _ => {
+            shape.push(Piece::Subtree(offset, subtree_size(node)));
+            return;
+        },
+    };
+    let left_size = subtree_size(node.left.as_deref());
+    shape.push(Piece::Node(offset + left_size, node.color));
+    // the search path is followed to its end, every node beside it for `REACH` levels
+    let child_reach = if path.is_some() { REACH } else { reach - 1 };
+    let child_path = |dir| match path {
+        Some(path) if path.first() == Some(&dir) => Some(&path[1..]),
+        _ => None,
+    };
+    capture(node.left.as_deref(), offset, child_path(Direction::Left), child_reach, shape);
+    capture(node.right.as_deref(), offset + left_size + 1, child_path(Direction::Right), child_reach, shape);
+}
+
+// A node the change reached has its value kept and its children taken apart as well, the inserted
+// node is dropped. The first node that was not reached is the top of a subtree no change touched.
+fn take_apart<T: Ord, A: Allocator>(
+    link: Link<T, A>,
+    offset: usize,
+    restored: &Restored<T>,
+    reached: &[usize],
+    parts: &mut Parts<T, A>,
+    observers: &mut Observers<T>,
+) {
+    let node = match link {
+        Some(node) => node,
+        None => return,
+    };
+    let index = offset + subtree_size(node.left.as_deref());
+    let before = restored.before(index);
+    if let Some(before) = before {
+        if reached.binary_search(&before).is_err() {
+            let start = restored.before(offset).expect("an untouched subtree can't hold the inserted value");
+            parts.subtrees.push((start, Some(node)));
+            return;
+        }
+    }
+    let node = Box::into_inner(node);
+    take_apart(node.left, offset, restored, reached, parts, observers);
+    match before {
+        Some(before) => parts.values.push((before, node.value)),
+        None => observers.notify(Event::Delete(&node.value)),
+    }
+    take_apart(node.right, index + 1, restored, reached, parts, observers);
+}
+
+fn put_together<'s, T: Ord, A: Allocator + Clone>(
+    pieces: &mut core::slice::Iter<'s, Piece>,
+    reached: &[usize],
+    values: &mut [Option<T>],
+    subtrees: &mut [(usize, Link<T, A>)],
+    alloc: &A,
+) -> Link<T, A> {
+    match pieces.next().expect("the shape is a whole tree") {
+        Piece::Subtree(_, 0) => None,
+        Piece::Subtree(start, size) => {
+            let at = subtrees.binary_search_by_key(start, |(start, _)| *start).expect("every untouched subtree is still in the tree");
+            let subtree = subtrees[at].1.take();
+            debug_assert_eq!(subtree_size(subtree.as_deref()), *size);
+            subtree
+        },
+        Piece::Node(index, color) => {
+            let at = reached.binary_search(index).expect("the shape only has reached nodes");
+            let mut node = Node::new(*color, values[at].take().expect("every value is used once"));
+            node.left = put_together(pieces, reached, values, subtrees, alloc);
+            node.right = put_together(pieces, reached, values, subtrees, alloc);
+            node.update_size();
+            Some(Box::new_in(node, alloc.clone()))
+        },
+    }
+}
+
+impl<'a, T: Ord, A: Allocator + Clone> Deref for Transaction<'a, T, A> {
+    type Target = RBTree<T, A>;
+
+    fn deref(&self) -> &RBTree<T, A> {
+        self.tree
+    }
+}
+
+// Also runs while unwinding, so a panicking closure leaves the tree as it was.
+impl<'a, T: Ord, A: Allocator + Clone> Drop for Transaction<'a, T, A> {
+    fn drop(&mut self) {
+        if !self.committed {
+            self.rollback();
+        }
+    }
+}
+
+impl<T: Ord, A: Allocator + Clone> RBTree<T, A> {
+    /// Applies the changes made by `f` to the tree only if it returns `Ok`.
+    ///
+    /// If `f` returns `Err` or panics, the tree is left exactly as it was before, down to the
+    /// shape and the colors of its nodes. Undoing a change costs about as much as making it.
+    pub fn transaction<R, E, F>(&mut self, f: F) -> Result<R, E>
+    where
+        F: FnOnce(&mut Transaction<'_, T, A>) -> Result<R, E>,
+    {
+        let mut tx = Transaction { tree: self, undo_log: Vec::new(), committed: false };
+        let result = f(&mut tx);
+        tx.committed = result.is_ok();
+        result
+    }
+}
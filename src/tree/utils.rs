@@ -1,19 +1,29 @@
+use allocator_api2::alloc::Allocator;
+
 use super::node::Node;
 
+/// The color of a node in a red-black tree.
 #[derive(PartialEq, Eq, Clone, Copy, Debug)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
 pub enum Color {
     Red,
     Black,
 }
 
-pub fn get_color<T: Ord>(node_or_leaf: Option<&Box<Node<T>>>) -> Color {
+pub fn get_color<T: Ord, A: Allocator>(node_or_leaf: Option<&Node<T, A>>) -> Color {
     match node_or_leaf {
         Some(node) => node.color,
         None => Color::Black,
     }
 }
 
+pub fn subtree_size<T: Ord, A: Allocator>(node_or_leaf: Option<&Node<T, A>>) -> usize {
+    node_or_leaf.map_or(0, |node| node.size)
+}
+
+/// Which child of a node to follow.
 #[derive(PartialEq, Eq, Clone, Copy, Debug)]
+#[cfg_attr(feature = "serde", derive(::serde::Serialize, ::serde::Deserialize))]
 pub enum Direction {
     Left,
     Right,
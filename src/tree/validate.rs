@@ -0,0 +1,165 @@
+use super::*;
+
+/// A broken red-black tree invariant, found by `RBTree::validate`.
+///
+/// `path` leads from the root to the offending node.
+#[derive(Debug, PartialEq, Eq)]
+pub enum InvariantViolation<'a, T> {
+    /// The root node is red.
+    RedRoot { value: &'a T },
+    /// A red node has a red child. `value` and `path` point to the child.
+    RedRed { value: &'a T, path: Vec<Direction> },
+    /// The subtrees of a node have a different number of black nodes on their paths to the leaves.
+    BlackHeight { value: &'a T, path: Vec<Direction>, left: usize, right: usize },
+    /// A value is out of order with respect to one of its ancestors.
+    Order { value: &'a T, path: Vec<Direction> },
+    /// A red node is a right child, which only `LLRBTree` forbids. `value` and `path` point to the child.
+    RightRed { value: &'a T, path: Vec<Direction> },
+    /// The ranks of the node's children differ from its own by amounts the balancing policy of a
+    /// `BalancedTree` does not allow. A missing child has rank -1.
+    RankDifference { value: &'a T, path: Vec<Direction>, left: i32, right: i32 },
+    /// The size stored in a node is not the number of nodes in its subtree.
+    Size { value: &'a T, path: Vec<Direction>, stored: usize, actual: usize },
+}
+
+impl<'a, T: fmt::Debug> fmt::Display for InvariantViolation<'a, T> {
+    fn fmt(&self, formatter: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            InvariantViolation::RedRoot { value } => write!(formatter, "root node {:?} is red", value),
+            InvariantViolation::RedRed { value, path } => write!(formatter, "red node {:?} at {:?} has a red parent", value, path),
+            InvariantViolation::BlackHeight { value, path, left, right } => write!(formatter, "node {:?} at {:?} has black height {} on the left and {} on the right", value, path, left, right),
+            InvariantViolation::Order { value, path } => write!(formatter, "node {:?} at {:?} is out of order", value, path),
+            InvariantViolation::RightRed { value, path } => write!(formatter, "red node {:?} at {:?} is a right child", value, path),
+            InvariantViolation::RankDifference { value, path, left, right } => write!(formatter, "node {:?} at {:?} has rank differences {} and {}", value, path, left, right),
+            InvariantViolation::Size { value, path, stored, actual } => write!(formatter, "node {:?} at {:?} stores size {} but has {} nodes", value, path, stored, actual),
+        }
+    }
+}
+
+impl<'a, T: fmt::Debug> core::error::Error for InvariantViolation<'a, T> {}
+
+impl<T: Ord, A: Allocator> RBTree<T, A> {
+    /// Checks the red-black and ordering invariants and the subtree sizes of the whole tree in O(n).
+    pub fn validate(&self) -> Result<(), InvariantViolation<'_, T>> {
+        validate_colored(self.root.as_deref())?;
+        validate_sizes(self.root.as_deref(), &mut Vec::new()).map(|_| ())
+    }
+}
+
+// Returns the number of nodes in the subtree.
+pub fn validate_sizes<'a, N: SizedNode>(node_or_leaf: Option<&'a N>, path: &mut Vec<Direction>) -> Result<usize, InvariantViolation<'a, N::Value>> {
+    let node = match node_or_leaf {
+        Some(node) => node,
+        None => return Ok(0),
+    };
+    path.push(Direction::Left);
+    let left = validate_sizes(node.child(Direction::Left), path)?;
+    path.pop();
+    path.push(Direction::Right);
+    let right = validate_sizes(node.child(Direction::Right), path)?;
+    path.pop();
+    let actual = left + right + 1;
+    if node.size() != actual {
+        return Err(InvariantViolation::Size { value: node.value(), path: path.clone(), stored: node.size(), actual });
+    }
+    Ok(actual)
+}
+
+// Checks that every value is in order with respect to its ancestors, for trees without colors.
+pub fn validate_order<'a, N: SearchNode>(node: &'a N, path: &mut Vec<Direction>, lower: Option<&N::Value>, upper: Option<&N::Value>) -> Result<(), InvariantViolation<'a, N::Value>> {
+    let value = node.value();
+    if lower.is_some_and(|lower| value < lower) || upper.is_some_and(|upper| value > upper) {
+        return Err(InvariantViolation::Order { value, path: path.clone() });
+    }
+    if let Some(left) = node.child(Direction::Left) {
+        path.push(Direction::Left);
+        validate_order(left, path, lower, Some(value))?;
+        path.pop();
+    }
+    if let Some(right) = node.child(Direction::Right) {
+        path.push(Direction::Right);
+        validate_order(right, path, Some(value), upper)?;
+        path.pop();
+    }
+    Ok(())
+}
+
+// Read access to a node of a binary search tree, so trees with other node types can share the
+// checks and the lookups.
+pub trait SearchNode {
+    type Value: Ord;
+
+    fn value(&self) -> &Self::Value;
+    fn child(&self, dir: Direction) -> Option<&Self>;
+}
+
+// A red-black node.
+pub trait ColoredNode: SearchNode {
+    fn color(&self) -> Color;
+}
+
+impl<T: Ord, A: Allocator> SearchNode for Node<T, A> {
+    type Value = T;
+
+    fn value(&self) -> &T {
+        &self.value
+    }
+
+    fn child(&self, dir: Direction) -> Option<&Self> {
+        match dir {
+            Direction::Left => self.left.as_deref(),
+            Direction::Right => self.right.as_deref(),
+        }
+    }
+}
+
+impl<T: Ord, A: Allocator> ColoredNode for Node<T, A> {
+    fn color(&self) -> Color {
+        self.color
+    }
+}
+
+pub fn validate_colored<N: ColoredNode>(root: Option<&N>) -> Result<(), InvariantViolation<'_, N::Value>> {
+    match root {
+        Some(root) => {
+            if root.color() == Color::Red {
+                return Err(InvariantViolation::RedRoot { value: root.value() });
+            }
+            validate_subtree(root, &mut Vec::new(), None, None).map(|_| ())
+        },
+        None => Ok(()),
+    }
+}
+
+// Returns the black height of the subtree, leaves excluded.
+fn validate_subtree<'a, N: ColoredNode>(node: &'a N, path: &mut Vec<Direction>, lower: Option<&N::Value>, upper: Option<&N::Value>) -> Result<usize, InvariantViolation<'a, N::Value>> {
+    let value = node.value();
+    let below_lower = lower.is_some_and(|lower| value < lower);
+    let above_upper = upper.is_some_and(|upper| value > upper);
+    if below_lower || above_upper {
+        return Err(InvariantViolation::Order { value, path: path.clone() });
+    }
+
+    let mut heights = [0, 0];
+    for (i, dir) in [Direction::Left, Direction::Right].iter().enumerate() {
+        if let Some(child) = node.child(*dir) {
+            path.push(*dir);
+            if node.color() == Color::Red && child.color() == Color::Red {
+                return Err(InvariantViolation::RedRed { value: child.value(), path: path.clone() });
+            }
+            heights[i] = match dir {
+                Direction::Left => validate_subtree(child, path, lower, Some(value))?,
+                Direction::Right => validate_subtree(child, path, Some(value), upper)?,
+            };
+            path.pop();
+        }
+    }
+
+    if heights[0] != heights[1] {
+        return Err(InvariantViolation::BlackHeight { value, path: path.clone(), left: heights[0], right: heights[1] });
+    }
+    match node.color() {
+        Color::Red => Ok(heights[0]),
+        Color::Black => Ok(heights[0] + 1),
+    }
+}